@@ -7,6 +7,6 @@ async fn main() -> Result<()> {
     // 这里对应 chat_server 当前常见的单轮调用方式。
     let message = vec![Message::user("Hello")];
     let response = adapter.complete(&message).await?;
-    println!("response: {}", response);
+    println!("response: {}", response.content);
     Ok(())
 }
@@ -0,0 +1,12 @@
+use ai_sdk::{AiService, Message, OpenAiAdapter};
+use anyhow::Result;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // 读取 OPENAI_BASE_URL / OPENAI_API_KEY，可指向 vLLM、llama.cpp server 等兼容服务。
+    let adapter = OpenAiAdapter::from_env("gpt-4o-mini");
+    let message = vec![Message::user("Hello")];
+    let response = adapter.complete(&message).await?;
+    println!("response: {}", response.content);
+    Ok(())
+}
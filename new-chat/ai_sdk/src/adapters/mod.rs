@@ -1,3 +1,114 @@
 mod ollama;
+mod openai;
+mod retry;
+mod scripted;
+mod test;
 
 pub use ollama::*;
+pub use openai::*;
+pub use retry::*;
+pub use scripted::*;
+pub use test::*;
+
+use crate::{AiError, Tool};
+use anyhow::Result;
+use futures_util::{Stream, StreamExt, stream::BoxStream};
+use reqwest::{RequestBuilder, Response, header::RETRY_AFTER};
+use serde::Serialize;
+use std::time::Duration;
+
+/// `{"type": "function", "function": {...}}`, how Ollama and OpenAI take tool definitions
+#[derive(Serialize)]
+pub struct FunctionTool {
+    pub r#type: String,
+    pub function: Tool,
+}
+
+impl From<&Tool> for FunctionTool {
+    fn from(tool: &Tool) -> Self {
+        Self {
+            r#type: "function".to_string(),
+            function: tool.clone(),
+        }
+    }
+}
+
+/// Send a request, turning transport failures and error statuses into an [`AiError`]
+/// that keeps the server's error body.
+pub(crate) async fn send(builder: RequestBuilder) -> Result<Response, AiError> {
+    let response = builder.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    // only the delay in seconds form is supported, not the http date one
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_secs);
+    let body = response.text().await.unwrap_or_default();
+    Err(AiError::from_status(
+        status,
+        format!("{} {}", status, body.trim()),
+        retry_after,
+    ))
+}
+
+/// Split a chunked byte stream into trimmed, non-empty lines. A line may span
+/// several chunks, so bytes are buffered until a newline (or the end of the
+/// stream) is seen.
+pub(crate) fn byte_lines<S, B, E>(stream: S) -> BoxStream<'static, Result<String>>
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send + 'static,
+    E: Into<anyhow::Error> + Send + 'static,
+{
+    let state = (Box::pin(stream), Vec::<u8>::new(), false);
+    futures_util::stream::unfold(state, |(mut stream, mut buf, mut eof)| async move {
+        loop {
+            let line = match buf.iter().position(|b| *b == b'\n') {
+                Some(pos) => buf.drain(..=pos).collect::<Vec<_>>(),
+                None if eof && !buf.is_empty() => std::mem::take(&mut buf),
+                None if eof => return None,
+                None => {
+                    match stream.next().await {
+                        Some(Ok(chunk)) => buf.extend_from_slice(chunk.as_ref()),
+                        Some(Err(e)) => {
+                            buf.clear();
+                            return Some((Err(e.into()), (stream, buf, true)));
+                        }
+                        None => eof = true,
+                    }
+                    continue;
+                }
+            };
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+            if !line.is_empty() {
+                return Some((Ok(line), (stream, buf, eof)));
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{TryStreamExt, stream};
+
+    #[tokio::test]
+    async fn byte_lines_should_join_partial_chunks() {
+        let chunks = vec![
+            Ok::<_, anyhow::Error>("{\"a\":".as_bytes()),
+            Ok("1}\n\n{\"b\"".as_bytes()),
+            Ok(":2}\n{\"c\":3}".as_bytes()),
+        ];
+        let lines: Vec<String> = byte_lines(stream::iter(chunks))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(lines, vec!["{\"a\":1}", "{\"b\":2}", "{\"c\":3}"]);
+    }
+}
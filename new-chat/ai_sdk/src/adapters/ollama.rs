@@ -1,11 +1,18 @@
-use crate::{AiAdapter, AiService, Message};
-use anyhow::Result;
-use reqwest::Client;
+use crate::{
+    AiAdapter, AiService, Completion, CompletionChunk, CompletionOptions, CompletionStream,
+    Message, Tool, ToolCall, Usage,
+    adapters::{FunctionTool, byte_lines, send},
+};
+use anyhow::{Result, anyhow};
+use futures_util::{Stream, StreamExt, TryStreamExt, stream};
+use reqwest::{Client, RequestBuilder};
 use serde::{Deserialize, Serialize};
+use std::env;
 
 pub struct OllamaAdapter {
     pub host: String,
     pub model: String,
+    pub options: CompletionOptions,
     pub client: Client,
 }
 
@@ -14,12 +21,47 @@ pub struct OllamaChatCompletionRequest {
     pub model: String,
     pub messages: Vec<OllamaMessage>,
     pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<OllamaOptions>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<FunctionTool>,
+}
+
+/// Ollama's `options` object (a subset of the modelfile parameters)
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct OllamaMessage {
     pub role: String,
+    #[serde(default)]
     pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OllamaToolCall>,
+    /// for `tool` messages, the function this is the result of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunctionCall,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OllamaFunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: serde_json::Value,
 }
 
 #[derive(Deserialize)]
@@ -28,14 +70,51 @@ pub struct OllamaChatCompletionResponse {
     pub created_at: String,
     pub message: OllamaMessage,
     pub done: bool,
+    // statistics are left out for e.g. a cached prompt
+    #[serde(default)]
     pub total_duration: u64,
+    #[serde(default)]
     pub load_duration: u64,
+    #[serde(default)]
     pub prompt_eval_count: u32,
+    #[serde(default)]
     pub prompt_eval_duration: u64,
+    #[serde(default)]
     pub eval_count: u32,
+    #[serde(default)]
     pub eval_duration: u64,
 }
 
+#[derive(Serialize)]
+pub struct OllamaEmbedRequest {
+    pub model: String,
+    pub input: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OllamaEmbedResponse {
+    pub model: String,
+    pub embeddings: Vec<Vec<f32>>,
+}
+
+/// One line of Ollama's NDJSON stream. The final chunk has `done` set and
+/// carries the timing / token statistics.
+#[derive(Deserialize)]
+pub struct OllamaChatCompletionChunk {
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub message: Option<OllamaMessage>,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub prompt_eval_count: Option<u32>,
+    #[serde(default)]
+    pub eval_count: Option<u32>,
+}
+
 impl OllamaAdapter {
     pub fn new(host: impl Into<String>, model: impl Into<String>) -> Self {
         let client = Client::builder()
@@ -43,9 +122,11 @@ impl OllamaAdapter {
             .no_proxy()
             .build()
             .expect("failed to build Ollama HTTP client");
+        let host: String = host.into();
         Self {
-            host: host.into(),
+            host: host.trim_end_matches('/').to_string(),
             model: model.into(),
+            options: CompletionOptions::default(),
             client,
         }
     }
@@ -53,6 +134,36 @@ impl OllamaAdapter {
     pub fn new_local(model: impl Into<String>) -> Self {
         Self::new("http://localhost:11434", model)
     }
+
+    /// Build an adapter from `OLLAMA_HOST`, falling back to the local server.
+    pub fn from_env(model: impl Into<String>) -> Self {
+        match env::var("OLLAMA_HOST") {
+            Ok(host) => Self::new(host, model),
+            Err(_) => Self::new_local(model),
+        }
+    }
+
+    pub fn with_options(mut self, options: CompletionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    fn request(&self, messages: &[Message], tools: &[Tool], stream: bool) -> RequestBuilder {
+        let options = OllamaOptions::from(&self.options);
+        let request = OllamaChatCompletionRequest {
+            model: self.model.clone(),
+            messages: messages.iter().map(|message| message.into()).collect(),
+            stream,
+            options: (options != OllamaOptions::default()).then_some(options),
+            tools: tools.iter().map(|tool| tool.into()).collect(),
+        };
+        let url = format!("{}/api/chat", self.host);
+        let builder = self.client.post(url).json(&request);
+        match self.options.timeout {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        }
+    }
 }
 
 impl Default for OllamaAdapter {
@@ -62,17 +173,79 @@ impl Default for OllamaAdapter {
 }
 
 impl AiService for OllamaAdapter {
-    async fn complete(&self, messages: &[Message]) -> Result<String> {
-        let request = OllamaChatCompletionRequest {
+    async fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        let response = send(self.request(messages, &[], false)).await?;
+        let response: OllamaChatCompletionResponse = response.json().await?;
+        Ok(response.into())
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Completion> {
+        let response = send(self.request(messages, tools, false)).await?;
+        let response: OllamaChatCompletionResponse = response.json().await?;
+        Ok(response.into())
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let request = OllamaEmbedRequest {
             model: self.model.clone(),
-            messages: messages.iter().map(|message| message.into()).collect(),
-            stream: false,
+            input: texts.to_vec(),
         };
-        let url = format!("{}/api/chat", self.host);
-        let response = self.client.post(url).json(&request).send().await?;
-        let response: OllamaChatCompletionResponse = response.json().await?;
-        Ok(response.message.content)
+        let url = format!("{}/api/embed", self.host);
+        let mut builder = self.client.post(url).json(&request);
+        if let Some(timeout) = self.options.timeout {
+            builder = builder.timeout(timeout);
+        }
+        let response = send(builder).await?;
+        let response: OllamaEmbedResponse = response.json().await?;
+        if response.embeddings.len() != texts.len() {
+            return Err(anyhow!(
+                "ollama returned {} embeddings for {} texts",
+                response.embeddings.len(),
+                texts.len()
+            ));
+        }
+        Ok(response.embeddings)
     }
+
+    async fn complete_stream(&self, messages: &[Message]) -> Result<CompletionStream> {
+        let response = send(self.request(messages, &[], true)).await?;
+        Ok(parse_ollama_stream(response.bytes_stream()))
+    }
+}
+
+/// Turn Ollama's NDJSON byte stream into a stream of content deltas, the
+/// token counts of the final chunk become the usage.
+pub fn parse_ollama_stream<S, B, E>(stream: S) -> CompletionStream
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send + 'static,
+    E: Into<anyhow::Error> + Send + 'static,
+{
+    byte_lines(stream)
+        .and_then(|line| async move {
+            let chunk: OllamaChatCompletionChunk = serde_json::from_str(&line)?;
+            if let Some(error) = chunk.error {
+                return Err(anyhow!("ollama stream error: {}", error));
+            }
+            let mut chunks = vec![];
+            let content = chunk.message.map(|m| m.content).unwrap_or_default();
+            if !content.is_empty() {
+                chunks.push(Ok(CompletionChunk::Delta(content)));
+            }
+            if chunk.done {
+                chunks.push(Ok(CompletionChunk::Usage(Usage {
+                    prompt_tokens: chunk.prompt_eval_count.unwrap_or_default(),
+                    completion_tokens: chunk.eval_count.unwrap_or_default(),
+                })));
+            }
+            Ok(stream::iter(chunks))
+        })
+        .try_flatten()
+        .boxed()
 }
 
 impl From<OllamaAdapter> for AiAdapter {
@@ -81,12 +254,20 @@ impl From<OllamaAdapter> for AiAdapter {
     }
 }
 
+impl From<&CompletionOptions> for OllamaOptions {
+    fn from(options: &CompletionOptions) -> Self {
+        OllamaOptions {
+            temperature: options.temperature,
+            top_p: options.top_p,
+            num_predict: options.max_tokens,
+            stop: options.stop.clone(),
+        }
+    }
+}
+
 impl From<Message> for OllamaMessage {
     fn from(message: Message) -> Self {
-        OllamaMessage {
-            role: message.role.to_string(),
-            content: message.content,
-        }
+        OllamaMessage::from(&message)
     }
 }
 
@@ -95,6 +276,49 @@ impl From<&Message> for OllamaMessage {
         OllamaMessage {
             role: message.role.to_string(),
             content: message.content.clone(),
+            tool_calls: message
+                .tool_calls
+                .iter()
+                .map(|call| OllamaToolCall {
+                    function: OllamaFunctionCall {
+                        name: call.name.clone(),
+                        arguments: call.arguments.clone(),
+                    },
+                })
+                .collect(),
+            tool_name: message.tool_name.clone(),
+        }
+    }
+}
+
+impl From<OllamaChatCompletionResponse> for Completion {
+    fn from(response: OllamaChatCompletionResponse) -> Self {
+        Completion {
+            usage: Usage {
+                prompt_tokens: response.prompt_eval_count,
+                completion_tokens: response.eval_count,
+            },
+            ..response.message.into()
+        }
+    }
+}
+
+// ollama doesn't give tool calls an id, number them in order
+impl From<OllamaMessage> for Completion {
+    fn from(message: OllamaMessage) -> Self {
+        Completion {
+            content: message.content,
+            tool_calls: message
+                .tool_calls
+                .into_iter()
+                .enumerate()
+                .map(|(i, call)| ToolCall {
+                    id: format!("call_{}", i),
+                    name: call.function.name,
+                    arguments: call.function.arguments,
+                })
+                .collect(),
+            usage: Usage::default(),
         }
     }
 }
@@ -113,17 +337,149 @@ mod tests {
         assert_eq!(converted.content, "Translate to Chinese");
     }
 
+    #[test]
+    fn request_should_carry_options() {
+        let adapter = OllamaAdapter::new("http://ollama:11434/", "llama3.2");
+        let request = adapter
+            .request(&[Message::user("Hi")], &[], false)
+            .build()
+            .unwrap();
+        assert_eq!(request.url().as_str(), "http://ollama:11434/api/chat");
+        let body: serde_json::Value =
+            serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert!(body.get("options").is_none());
+
+        let adapter = adapter.with_options(CompletionOptions {
+            temperature: Some(0.5),
+            max_tokens: Some(128),
+            stop: vec!["\n\n".to_string()],
+            ..Default::default()
+        });
+        let request = adapter
+            .request(&[Message::user("Hi")], &[], false)
+            .build()
+            .unwrap();
+        let body: serde_json::Value =
+            serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(
+            body["options"],
+            serde_json::json!({ "temperature": 0.5, "num_predict": 128, "stop": ["\n\n"] })
+        );
+    }
+
+    #[test]
+    fn tool_calls_should_round_trip() {
+        let response = r#"{"role":"assistant","content":"","tool_calls":[{"function":{"name":"list_members","arguments":{}}}]}"#;
+        let message: OllamaMessage = serde_json::from_str(response).unwrap();
+        let completion = Completion::from(message);
+        assert_eq!(completion.tool_calls[0].id, "call_0");
+        assert_eq!(completion.tool_calls[0].name, "list_members");
+
+        let call = completion.tool_calls[0].clone();
+        let messages = [Message::from(completion), Message::tool(&call, "[]")];
+        let tool = Tool {
+            name: "list_members".to_string(),
+            description: "List chat members".to_string(),
+            parameters: serde_json::json!({ "type": "object", "properties": {} }),
+        };
+        let request = OllamaAdapter::new_local("llama3.2")
+            .request(&messages, &[tool], false)
+            .build()
+            .unwrap();
+        let body: serde_json::Value =
+            serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "list_members");
+        assert_eq!(
+            body["messages"][0]["tool_calls"][0]["function"]["name"],
+            "list_members"
+        );
+        assert_eq!(
+            body["messages"][1],
+            serde_json::json!({ "role": "tool", "content": "[]", "tool_name": "list_members" })
+        );
+    }
+
+    #[tokio::test]
+    async fn ollama_embed_should_work() {
+        use axum::{Json, Router, routing::post};
+        use serde_json::{Value, json};
+
+        async fn embed(Json(body): Json<Value>) -> Json<Value> {
+            let embeddings = body["input"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|text| json!([text.as_str().unwrap().len() as f32, 0.5]))
+                .collect::<Vec<_>>();
+            Json(json!({ "model": body["model"], "embeddings": embeddings }))
+        }
+
+        let app = Router::new().route("/api/embed", post(embed));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let adapter = OllamaAdapter::new(format!("http://{}", addr), "nomic-embed-text");
+        let vectors = adapter
+            .embed(&["hi".to_string(), "hello".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(vectors, vec![vec![2.0, 0.5], vec![5.0, 0.5]]);
+    }
+
+    #[tokio::test]
+    async fn parse_ollama_stream_should_yield_deltas() {
+        let body = concat!(
+            r#"{"model":"llama3.2","created_at":"2026-01-01T00:00:00Z","message":{"role":"assistant","content":"Hel"},"done":false}"#,
+            "\n",
+            r#"{"model":"llama3.2","created_at":"2026-01-01T00:00:00Z","message":{"role":"assistant","content":"lo"},"done":false}"#,
+            "\n",
+            r#"{"model":"llama3.2","created_at":"2026-01-01T00:00:00Z","message":{"role":"assistant","content":""},"done":true,"eval_count":2}"#,
+            "\n",
+        );
+        // split in the middle of a line to simulate network chunking
+        let (a, b) = body.split_at(40);
+        let chunks = vec![Ok::<_, anyhow::Error>(a.as_bytes()), Ok(b.as_bytes())];
+        let chunks: Vec<CompletionChunk> = parse_ollama_stream(stream::iter(chunks))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                CompletionChunk::Delta("Hel".to_string()),
+                CompletionChunk::Delta("lo".to_string()),
+                CompletionChunk::Usage(Usage {
+                    prompt_tokens: 0,
+                    completion_tokens: 2
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_ollama_stream_should_surface_errors() {
+        let chunks = vec![Ok::<_, anyhow::Error>(
+            r#"{"error":"model not found"}"#.as_bytes(),
+        )];
+        let ret: Result<Vec<CompletionChunk>> = parse_ollama_stream(stream::iter(chunks))
+            .try_collect()
+            .await;
+        assert_eq!(
+            ret.unwrap_err().to_string(),
+            "ollama stream error: model not found"
+        );
+    }
+
     // 这个测试依赖本地可访问的 Ollama 服务（http://localhost:11434），因此默认忽略。
     // 运行命令：cargo nextest run --run-ignored ignored-only ollama_complete_should_work
     #[ignore]
     #[tokio::test]
     async fn ollama_complete_should_work() {
         let adapter = OllamaAdapter::new_local("llama3.2");
-        let messages = vec![Message {
-            role: Role::User,
-            content: "Hello".to_string(),
-        }];
+        let messages = vec![Message::new(Role::User, "Hello")];
         let response = adapter.complete(&messages).await.unwrap();
-        println!("response: {}", response);
+        println!("response: {:?}", response);
     }
 }
@@ -0,0 +1,575 @@
+use crate::{
+    AiAdapter, AiService, Completion, CompletionChunk, CompletionOptions, CompletionStream,
+    Message, Tool, ToolCall, Usage,
+    adapters::{FunctionTool, byte_lines, send},
+};
+use anyhow::{Result, anyhow};
+use futures_util::{Stream, StreamExt, TryStreamExt, future, stream};
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::env;
+
+/// Adapter for any OpenAI compatible `/v1/chat/completions` endpoint
+/// (OpenAI, vLLM, llama.cpp server, ...).
+pub struct OpenAiAdapter {
+    pub base_url: String,
+    pub api_key: String,
+    pub model: String,
+    pub options: CompletionOptions,
+    pub client: Client,
+}
+
+#[derive(Serialize)]
+pub struct OpenAiChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<OpenAiMessage>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<FunctionTool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<OpenAiStreamOptions>,
+}
+
+/// asks for a last chunk carrying the usage of a streamed completion
+#[derive(Serialize)]
+pub struct OpenAiStreamOptions {
+    pub include_usage: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OpenAiMessage {
+    pub role: String,
+    // null when the assistant only calls tools
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OpenAiToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OpenAiToolCall {
+    pub id: String,
+    pub r#type: String,
+    pub function: OpenAiFunctionCall,
+}
+
+/// `arguments` is a JSON encoded string
+#[derive(Serialize, Deserialize)]
+pub struct OpenAiFunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiChatCompletionResponse {
+    pub id: String,
+    pub model: String,
+    pub created: u64,
+    pub choices: Vec<OpenAiChoice>,
+    #[serde(default)]
+    pub usage: Option<OpenAiUsage>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiChoice {
+    pub index: u32,
+    pub message: OpenAiMessage,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+/// One `data:` event of a streaming chat completion.
+#[derive(Deserialize)]
+pub struct OpenAiChatCompletionChunk {
+    #[serde(default)]
+    pub choices: Vec<OpenAiChunkChoice>,
+    /// only in the last chunk, with no choices
+    #[serde(default)]
+    pub usage: Option<OpenAiUsage>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiChunkChoice {
+    pub delta: OpenAiDelta,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiDelta {
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct OpenAiEmbeddingRequest {
+    pub model: String,
+    pub input: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiEmbeddingResponse {
+    pub data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiEmbedding {
+    pub index: usize,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl OpenAiAdapter {
+    pub fn new(
+        base_url: impl Into<String>,
+        api_key: impl Into<String>,
+        model: impl Into<String>,
+    ) -> Self {
+        let client = Client::builder()
+            .tls_backend_rustls()
+            .no_proxy()
+            .build()
+            .expect("failed to build OpenAI HTTP client");
+        let base_url: String = base_url.into();
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.into(),
+            model: model.into(),
+            options: CompletionOptions::default(),
+            client,
+        }
+    }
+
+    /// Build an adapter from `OPENAI_BASE_URL` and `OPENAI_API_KEY`,
+    /// falling back to the official OpenAI endpoint without a key.
+    pub fn from_env(model: impl Into<String>) -> Self {
+        let base_url =
+            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".into());
+        let api_key = env::var("OPENAI_API_KEY").unwrap_or_default();
+        Self::new(base_url, api_key, model)
+    }
+
+    pub fn with_options(mut self, options: CompletionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    fn request(&self, messages: &[Message], tools: &[Tool], stream: bool) -> RequestBuilder {
+        let request = OpenAiChatCompletionRequest {
+            model: self.model.clone(),
+            messages: messages.iter().map(|message| message.into()).collect(),
+            stream,
+            temperature: self.options.temperature,
+            top_p: self.options.top_p,
+            max_tokens: self.options.max_tokens,
+            stop: self.options.stop.clone(),
+            tools: tools.iter().map(|tool| tool.into()).collect(),
+            stream_options: stream.then_some(OpenAiStreamOptions {
+                include_usage: true,
+            }),
+        };
+        self.post("chat/completions", &request)
+    }
+
+    fn post(&self, path: &str, body: &impl Serialize) -> RequestBuilder {
+        let url = format!("{}/{}", self.base_url, path);
+        let mut builder = self.client.post(url).json(body);
+        if let Some(timeout) = self.options.timeout {
+            builder = builder.timeout(timeout);
+        }
+        // local servers like llama.cpp don't require a key
+        if self.api_key.is_empty() {
+            builder
+        } else {
+            builder.bearer_auth(&self.api_key)
+        }
+    }
+}
+
+impl AiService for OpenAiAdapter {
+    async fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        self.complete_with_tools(messages, &[]).await
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Completion> {
+        let response = send(self.request(messages, tools, false)).await?;
+        let mut response: OpenAiChatCompletionResponse = response.json().await?;
+        if response.choices.is_empty() {
+            return Err(anyhow!("no choices in OpenAI response {}", response.id));
+        }
+        let usage = response.usage.map(Usage::from).unwrap_or_default();
+        Ok(Completion {
+            usage,
+            ..response.choices.swap_remove(0).message.into()
+        })
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let request = OpenAiEmbeddingRequest {
+            model: self.model.clone(),
+            input: texts.to_vec(),
+        };
+        let response = send(self.post("embeddings", &request)).await?;
+        let mut response: OpenAiEmbeddingResponse = response.json().await?;
+        if response.data.len() != texts.len() {
+            return Err(anyhow!(
+                "OpenAI returned {} embeddings for {} texts",
+                response.data.len(),
+                texts.len()
+            ));
+        }
+        // the order of `data` isn't guaranteed, `index` is
+        response.data.sort_by_key(|e| e.index);
+        Ok(response.data.into_iter().map(|e| e.embedding).collect())
+    }
+
+    async fn complete_stream(&self, messages: &[Message]) -> Result<CompletionStream> {
+        let response = send(self.request(messages, &[], true)).await?;
+        Ok(parse_openai_stream(response.bytes_stream()))
+    }
+}
+
+/// Turn an OpenAI server-sent event stream into a stream of content deltas,
+/// followed by the usage if the server sends it.
+pub fn parse_openai_stream<S, B, E>(stream: S) -> CompletionStream
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send + 'static,
+    E: Into<anyhow::Error> + Send + 'static,
+{
+    byte_lines(stream)
+        .try_take_while(|line| future::ok(line != "data: [DONE]"))
+        .try_filter_map(|line| async move {
+            // ignore comments / other sse fields, only `data:` carries chunks
+            let Some(data) = line.strip_prefix("data:") else {
+                return Ok(None);
+            };
+            let chunk: OpenAiChatCompletionChunk = serde_json::from_str(data.trim())?;
+            let mut chunks = vec![];
+            let content = chunk
+                .choices
+                .into_iter()
+                .filter_map(|choice| choice.delta.content)
+                .collect::<String>();
+            if !content.is_empty() {
+                chunks.push(Ok(CompletionChunk::Delta(content)));
+            }
+            if let Some(usage) = chunk.usage {
+                chunks.push(Ok(CompletionChunk::Usage(usage.into())));
+            }
+            Ok(Some(stream::iter(chunks)))
+        })
+        .try_flatten()
+        .boxed()
+}
+
+impl From<OpenAiAdapter> for AiAdapter {
+    fn from(adapter: OpenAiAdapter) -> Self {
+        AiAdapter::OpenAi(adapter)
+    }
+}
+
+impl From<Message> for OpenAiMessage {
+    fn from(message: Message) -> Self {
+        OpenAiMessage::from(&message)
+    }
+}
+
+impl From<&Message> for OpenAiMessage {
+    fn from(message: &Message) -> Self {
+        OpenAiMessage {
+            role: message.role.to_string(),
+            content: message.content.clone(),
+            tool_calls: message
+                .tool_calls
+                .iter()
+                .map(|call| OpenAiToolCall {
+                    id: call.id.clone(),
+                    r#type: "function".to_string(),
+                    function: OpenAiFunctionCall {
+                        name: call.name.clone(),
+                        arguments: call.arguments.to_string(),
+                    },
+                })
+                .collect(),
+            tool_call_id: message.tool_call_id.clone(),
+        }
+    }
+}
+
+impl From<OpenAiMessage> for Completion {
+    fn from(message: OpenAiMessage) -> Self {
+        Completion {
+            content: message.content,
+            tool_calls: message
+                .tool_calls
+                .into_iter()
+                .map(|call| ToolCall {
+                    id: call.id,
+                    name: call.function.name,
+                    // models sometimes send no or broken arguments, keep what we got
+                    arguments: match call.function.arguments.trim() {
+                        "" => serde_json::json!({}),
+                        args => serde_json::from_str(args)
+                            .unwrap_or_else(|_| serde_json::Value::String(args.to_string())),
+                    },
+                })
+                .collect(),
+            usage: Usage::default(),
+        }
+    }
+}
+
+impl From<OpenAiUsage> for Usage {
+    fn from(usage: OpenAiUsage) -> Self {
+        Usage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        http::HeaderMap,
+        response::{IntoResponse, Response},
+        routing::post,
+    };
+    use serde_json::{Value, json};
+    use tokio::net::TcpListener;
+
+    async fn completions(headers: HeaderMap, Json(body): Json<Value>) -> Response {
+        if body["stream"] == json!(true) {
+            assert_eq!(body["stream_options"], json!({ "include_usage": true }));
+            let events = concat!(
+                ": keep-alive\n\n",
+                "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+                "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+                "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"}}]}\n\n",
+                "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":4,\"completion_tokens\":2,\"total_tokens\":6}}\n\n",
+                "data: [DONE]\n\n",
+            );
+            return ([("content-type", "text/event-stream")], events).into_response();
+        }
+
+        let auth = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let messages = body["messages"].as_array().cloned().unwrap_or_default();
+        let last = messages
+            .last()
+            .and_then(|m| m["content"].as_str())
+            .unwrap_or_default();
+        let model = body["model"].as_str().unwrap_or_default();
+        let last_role = messages.last().and_then(|m| m["role"].as_str());
+        if body.get("tools").is_some() && last_role != Some("tool") {
+            return Json(json!({
+                "id": "chatcmpl-2",
+                "created": 1700000000,
+                "model": model,
+                "choices": [{
+                    "index": 0,
+                    "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call_abc",
+                            "type": "function",
+                            "function": { "name": "list_members", "arguments": "{\"limit\":2}" }
+                        }]
+                    },
+                    "finish_reason": "tool_calls"
+                }]
+            }))
+            .into_response();
+        }
+        let mut content = format!("{}|{}|{}|{}", model, auth, messages.len(), last);
+        if let Some(temperature) = body.get("temperature") {
+            content = format!("{}|{}", content, temperature);
+        }
+        Json(json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": body["model"],
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": content
+                },
+                "finish_reason": "stop"
+            }],
+            "usage": { "prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8 }
+        }))
+        .into_response()
+    }
+
+    async fn embeddings(Json(body): Json<Value>) -> Json<Value> {
+        // reversed, to check the adapter restores the input order
+        let data = body["input"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(index, text)| {
+                json!({ "object": "embedding", "index": index, "embedding": [text.as_str().unwrap().len() as f32] })
+            })
+            .collect::<Vec<_>>();
+        Json(json!({ "object": "list", "model": body["model"], "data": data }))
+    }
+
+    async fn start_mock_server() -> String {
+        let app = Router::new()
+            .route("/v1/chat/completions", post(completions))
+            .route("/v1/embeddings", post(embeddings));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/v1", addr)
+    }
+
+    #[test]
+    fn message_conversion_preserves_role_and_content() {
+        let message = Message::assistant("Hi");
+        let converted = OpenAiMessage::from(&message);
+
+        assert_eq!(converted.role, "assistant");
+        assert_eq!(converted.content, "Hi");
+    }
+
+    #[tokio::test]
+    async fn openai_complete_should_work() {
+        let base_url = start_mock_server().await;
+        let adapter = OpenAiAdapter::new(format!("{}/", base_url), "sk-test", "qwen2.5");
+        let messages = vec![Message::system("Be brief"), Message::user("Hello")];
+        let response = adapter.complete(&messages).await.unwrap();
+        assert_eq!(response.content, "qwen2.5|Bearer sk-test|2|Hello");
+        assert_eq!(
+            response.usage,
+            Usage {
+                prompt_tokens: 5,
+                completion_tokens: 3
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn openai_complete_without_api_key_should_not_send_auth() {
+        let base_url = start_mock_server().await;
+        let adapter = OpenAiAdapter::new(base_url, "", "llama3.2");
+        let response = adapter.complete(&[Message::user("Hi")]).await.unwrap();
+        assert_eq!(response.content, "llama3.2||1|Hi");
+    }
+
+    #[tokio::test]
+    async fn openai_complete_should_send_options() {
+        let base_url = start_mock_server().await;
+        let adapter =
+            OpenAiAdapter::new(base_url, "", "llama3.2").with_options(CompletionOptions {
+                temperature: Some(0.5),
+                ..Default::default()
+            });
+        let response = adapter.complete(&[Message::user("Hi")]).await.unwrap();
+        assert_eq!(response.content, "llama3.2||1|Hi|0.5");
+    }
+
+    #[tokio::test]
+    async fn openai_complete_with_tools_should_work() {
+        let base_url = start_mock_server().await;
+        let adapter = OpenAiAdapter::new(base_url, "", "qwen2.5");
+        let tools = [Tool {
+            name: "list_members".to_string(),
+            description: "List chat members".to_string(),
+            parameters: json!({ "type": "object", "properties": { "limit": { "type": "integer" } } }),
+        }];
+        let mut messages = vec![Message::user("Who is here?")];
+        let completion = adapter
+            .complete_with_tools(&messages, &tools)
+            .await
+            .unwrap();
+        assert_eq!(completion.content, "");
+        assert_eq!(
+            completion.tool_calls,
+            vec![ToolCall {
+                id: "call_abc".to_string(),
+                name: "list_members".to_string(),
+                arguments: json!({ "limit": 2 }),
+            }]
+        );
+
+        let call = completion.tool_calls[0].clone();
+        messages.push(completion.into());
+        messages.push(Message::tool(&call, "Alice, Bob"));
+        let completion = adapter
+            .complete_with_tools(&messages, &tools)
+            .await
+            .unwrap();
+        assert!(completion.tool_calls.is_empty());
+        assert_eq!(completion.content, "qwen2.5||3|Alice, Bob");
+    }
+
+    #[tokio::test]
+    async fn openai_embed_should_work() {
+        let base_url = start_mock_server().await;
+        let adapter = OpenAiAdapter::new(base_url, "sk-test", "text-embedding-3-small");
+        let vectors = adapter
+            .embed(&["hi".to_string(), "hello".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(vectors, vec![vec![2.0], vec![5.0]]);
+    }
+
+    #[tokio::test]
+    async fn openai_complete_stream_should_work() {
+        let base_url = start_mock_server().await;
+        let adapter = OpenAiAdapter::new(base_url, "sk-test", "qwen2.5");
+        let chunks: Vec<CompletionChunk> = adapter
+            .complete_stream(&[Message::user("Hi")])
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                CompletionChunk::Delta("Hel".to_string()),
+                CompletionChunk::Delta("lo".to_string()),
+                CompletionChunk::Usage(Usage {
+                    prompt_tokens: 4,
+                    completion_tokens: 2
+                }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use crate::{AiAdapter, AiError, AiService, Completion, CompletionStream, Message, Tool};
+use anyhow::{Result, anyhow};
+use std::{future::Future, time::Duration};
+use tokio::time;
+
+/// How [`RetryAdapter`] retries a failing request
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// retries of each adapter after the first attempt
+    pub max_retries: u32,
+    /// delay before the first retry, doubled for every next one
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// bound of a single attempt, on top of the adapter's own request timeout
+    pub timeout: Option<Duration>,
+}
+
+/// Wraps an ordered list of adapters (e.g. the same server with a smaller model,
+/// or another server): each one is retried with exponential backoff while the
+/// error is retryable, then the next one is tried. The error of the last attempt
+/// is returned if they all fail.
+///
+/// Streams are only retried until they are established, not once deltas flow.
+pub struct RetryAdapter {
+    pub adapters: Vec<AiAdapter>,
+    pub policy: RetryPolicy,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            timeout: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// delay before the retry following `attempt` (0 based)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff)
+    }
+}
+
+impl RetryAdapter {
+    pub fn new(adapter: impl Into<AiAdapter>) -> Self {
+        Self {
+            adapters: vec![adapter.into()],
+            policy: RetryPolicy::default(),
+        }
+    }
+
+    /// try `adapter` once the previous ones failed
+    pub fn with_fallback(mut self, adapter: impl Into<AiAdapter>) -> Self {
+        self.adapters.push(adapter.into());
+        self
+    }
+
+    pub fn with_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    async fn run<'a, T, F, Fut>(&'a self, f: F) -> Result<T>
+    where
+        F: Fn(&'a AiAdapter) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut last_error = None;
+        for adapter in &self.adapters {
+            let mut attempt = 0;
+            loop {
+                let result = match self.policy.timeout {
+                    Some(timeout) => time::timeout(timeout, f(adapter))
+                        .await
+                        .unwrap_or_else(|_| Err(AiError::Timeout.into())),
+                    None => f(adapter).await,
+                };
+                let e = match result {
+                    Ok(value) => return Ok(value),
+                    Err(e) => e,
+                };
+                // errors that aren't an `AiError` (e.g. an unexpected response body)
+                // won't go away by asking the same server again
+                let delay = match e.downcast_ref::<AiError>() {
+                    Some(AiError::RateLimited {
+                        retry_after: Some(retry_after),
+                    }) => Some((*retry_after).min(self.policy.max_backoff)),
+                    Some(error) if error.is_retryable() => Some(self.policy.backoff(attempt)),
+                    _ => None,
+                };
+                last_error = Some(e);
+                match delay {
+                    Some(delay) if attempt < self.policy.max_retries => time::sleep(delay).await,
+                    _ => break,
+                }
+                attempt += 1;
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("no adapter to send the request to")))
+    }
+}
+
+impl AiService for RetryAdapter {
+    async fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        self.run(|adapter| adapter.complete(messages)).await
+    }
+
+    async fn complete_stream(&self, messages: &[Message]) -> Result<CompletionStream> {
+        self.run(|adapter| adapter.complete_stream(messages)).await
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Completion> {
+        self.run(|adapter| adapter.complete_with_tools(messages, tools))
+            .await
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.run(|adapter| adapter.embed(texts)).await
+    }
+}
+
+impl From<RetryAdapter> for AiAdapter {
+    fn from(adapter: RetryAdapter) -> Self {
+        AiAdapter::Retry(adapter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OllamaAdapter, Script, ScriptedAdapter};
+    use axum::{
+        Json, Router,
+        extract::State,
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        routing::post,
+    };
+    use serde_json::{Value, json};
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+    use tokio::net::TcpListener;
+
+    /// the statuses the mock server answers with, in order, then 200
+    #[derive(Clone)]
+    struct Mock {
+        statuses: Arc<Vec<u16>>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    async fn chat(State(mock): State<Mock>, Json(body): Json<Value>) -> Response {
+        let call = mock.calls.fetch_add(1, Ordering::SeqCst);
+        match mock.statuses.get(call) {
+            Some(0) => {
+                time::sleep(Duration::from_secs(5)).await;
+                StatusCode::OK.into_response()
+            }
+            Some(429) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [("retry-after", "0")],
+                "slow down",
+            )
+                .into_response(),
+            Some(status) => (StatusCode::from_u16(*status).unwrap(), "oops").into_response(),
+            None => Json(json!({
+                "model": body["model"],
+                "created_at": "2025-01-01T00:00:00Z",
+                "message": { "role": "assistant", "content": "hi" },
+                "done": true,
+                "total_duration": 0,
+                "load_duration": 0,
+                "prompt_eval_count": 1,
+                "prompt_eval_duration": 0,
+                "eval_count": 1,
+                "eval_duration": 0
+            }))
+            .into_response(),
+        }
+    }
+
+    async fn mock_server(statuses: Vec<u16>) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mock = Mock {
+            statuses: Arc::new(statuses),
+            calls: calls.clone(),
+        };
+        let app = Router::new()
+            .route("/api/chat", post(chat))
+            .with_state(mock);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}", addr), calls)
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(50),
+            timeout: Some(Duration::from_millis(500)),
+        }
+    }
+
+    #[test]
+    fn backoff_should_double_up_to_max() {
+        let policy = policy();
+        let delays = (0..4).map(|i| policy.backoff(i)).collect::<Vec<_>>();
+        assert_eq!(delays, [10, 20, 40, 50].map(Duration::from_millis).to_vec());
+    }
+
+    #[tokio::test]
+    async fn retry_adapter_should_retry_retryable_errors() {
+        let (host, calls) = mock_server(vec![503, 429]).await;
+        let adapter = RetryAdapter::new(OllamaAdapter::new(host, "llama3.2")).with_policy(policy());
+        let reply = adapter
+            .complete(&[Message::user("Hello")])
+            .await
+            .unwrap()
+            .content;
+        assert_eq!(reply, "hi");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_adapter_should_report_structured_errors() {
+        let (host, calls) = mock_server(vec![503, 503, 503]).await;
+        let adapter = RetryAdapter::new(OllamaAdapter::new(host, "llama3.2")).with_policy(policy());
+        let err = adapter
+            .complete(&[Message::user("Hello")])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<AiError>(),
+            Some(AiError::Unavailable(message)) if message.contains("oops")
+        ));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // bad requests are not retried
+        let (host, calls) = mock_server(vec![404]).await;
+        let adapter = RetryAdapter::new(OllamaAdapter::new(host, "nope")).with_policy(policy());
+        let err = adapter
+            .complete(&[Message::user("Hello")])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<AiError>(),
+            Some(AiError::BadRequest(_))
+        ));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // a slow server times out
+        let (host, _) = mock_server(vec![0, 0, 0]).await;
+        let adapter =
+            RetryAdapter::new(OllamaAdapter::new(host, "llama3.2")).with_policy(RetryPolicy {
+                max_retries: 0,
+                ..policy()
+            });
+        let err = adapter
+            .complete(&[Message::user("Hello")])
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<AiError>(), Some(&AiError::Timeout));
+    }
+
+    #[tokio::test]
+    async fn retry_adapter_should_fall_back_in_order() {
+        let (host, calls) = mock_server(vec![400]).await;
+        let fallback = ScriptedAdapter::try_new(Script::Fixed {
+            reply: "from fallback".to_string(),
+        })
+        .unwrap();
+        let adapter = RetryAdapter::new(OllamaAdapter::new(host, "llama3.2"))
+            .with_fallback(fallback)
+            .with_policy(policy());
+        let reply = adapter
+            .complete(&[Message::user("Hello")])
+            .await
+            .unwrap()
+            .content;
+        assert_eq!(reply, "from fallback");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // nothing listens on the first host
+        let adapter = RetryAdapter::new(OllamaAdapter::new("http://127.0.0.1:1", "llama3.2"))
+            .with_fallback(crate::TestAdapter::default())
+            .with_policy(policy());
+        let reply = adapter
+            .complete(&[Message::user("Hello")])
+            .await
+            .unwrap()
+            .content;
+        assert_eq!(reply, "Hello");
+    }
+}
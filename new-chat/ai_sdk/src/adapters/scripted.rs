@@ -0,0 +1,255 @@
+use crate::{
+    AiAdapter, AiError, AiService, Completion, CompletionChunk, CompletionStream, Message, Role,
+    TestAdapter, Tool,
+    adapters::test::{completion, completion_stream},
+};
+use anyhow::{Result, anyhow};
+use futures_util::{StreamExt, TryStreamExt, future, stream};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// What a [`ScriptedAdapter`] answers, e.g. `{"mode": "fixed", "reply": "Hi"}`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Script {
+    /// repeat the last user message
+    #[default]
+    Echo,
+    /// always the same reply
+    Fixed { reply: String },
+    /// replace all matches of `pattern` in the last user message
+    Rewrite {
+        pattern: String,
+        replacement: String,
+    },
+    /// the replies in order, going on after the ones already in the conversation and
+    /// starting over at the end
+    Sequence { replies: Vec<String> },
+    /// every call fails as if the model server was down
+    Fail { error: String },
+    /// stream `reply` and then hang without finishing, calls without streaming hang
+    Stall { reply: String },
+}
+
+/// Deterministic adapter answering from a [`Script`], so agents run without a model.
+/// Embeddings are the ones of [`TestAdapter`].
+#[derive(Debug)]
+pub struct ScriptedAdapter {
+    pub script: Script,
+    regex: Option<Regex>,
+}
+
+impl ScriptedAdapter {
+    /// fails if the script can't run: an invalid regex or an empty sequence
+    pub fn try_new(script: Script) -> Result<Self> {
+        let regex = match &script {
+            Script::Rewrite { pattern, .. } => Some(Regex::new(pattern)?),
+            Script::Sequence { replies } if replies.is_empty() => {
+                return Err(anyhow!("a sequence script needs at least one reply"));
+            }
+            _ => None,
+        };
+        Ok(Self { script, regex })
+    }
+
+    /// build from a JSON script, a missing script echoes
+    pub fn from_value(value: Option<&serde_json::Value>) -> Result<Self> {
+        let script = match value {
+            Some(value) => Script::deserialize(value)?,
+            None => Script::default(),
+        };
+        Self::try_new(script)
+    }
+
+    fn reply(&self, messages: &[Message]) -> Result<String> {
+        let last = messages
+            .iter()
+            .rev()
+            .find(|m| m.role == Role::User)
+            .map(|m| m.content.as_str())
+            .unwrap_or_default();
+        let reply = match &self.script {
+            Script::Echo => last.to_string(),
+            Script::Fixed { reply } | Script::Stall { reply } => reply.clone(),
+            Script::Rewrite { replacement, .. } => {
+                let regex = self.regex.as_ref().expect("rewrite regex is compiled");
+                regex.replace_all(last, replacement.as_str()).into_owned()
+            }
+            // adapters are built for every run, so the position comes from the conversation
+            Script::Sequence { replies } => {
+                let n = messages
+                    .iter()
+                    .filter(|m| m.role == Role::Assistant && replies.contains(&m.content))
+                    .count();
+                replies[n % replies.len()].clone()
+            }
+            Script::Fail { error } => return Err(AiError::Unavailable(error.clone()).into()),
+        };
+        Ok(reply)
+    }
+
+    async fn complete_or_stall(&self, messages: &[Message]) -> Result<Completion> {
+        let reply = self.reply(messages)?;
+        if matches!(self.script, Script::Stall { .. }) {
+            future::pending::<()>().await;
+        }
+        Ok(completion(messages, reply))
+    }
+}
+
+impl Default for ScriptedAdapter {
+    fn default() -> Self {
+        Self::try_new(Script::Echo).expect("echo script is valid")
+    }
+}
+
+impl AiService for ScriptedAdapter {
+    async fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        self.complete_or_stall(messages).await
+    }
+
+    async fn complete_stream(&self, messages: &[Message]) -> Result<CompletionStream> {
+        let stream = completion_stream(messages, self.reply(messages)?);
+        match self.script {
+            // the deltas, without the usage sent last
+            Script::Stall { .. } => Ok(stream
+                .try_take_while(|chunk| {
+                    future::ready(Ok(matches!(chunk, CompletionChunk::Delta(_))))
+                })
+                .chain(stream::pending())
+                .boxed()),
+            _ => Ok(stream),
+        }
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        _tools: &[Tool],
+    ) -> Result<Completion> {
+        self.complete_or_stall(messages).await
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        TestAdapter::default().embed(texts).await
+    }
+}
+
+impl From<ScriptedAdapter> for AiAdapter {
+    fn from(adapter: ScriptedAdapter) -> Self {
+        AiAdapter::Scripted(adapter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Usage;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn messages(content: &str) -> Vec<Message> {
+        vec![Message::system("be nice"), Message::user(content)]
+    }
+
+    #[tokio::test]
+    async fn scripted_adapter_should_follow_script() {
+        let adapter = ScriptedAdapter::default();
+        assert_eq!(
+            adapter.complete(&messages("hi")).await.unwrap().content,
+            "hi"
+        );
+
+        let script = json!({ "mode": "fixed", "reply": "I'm a bot" });
+        let adapter = ScriptedAdapter::from_value(Some(&script)).unwrap();
+        assert_eq!(
+            adapter.complete(&messages("hi")).await.unwrap().content,
+            "I'm a bot"
+        );
+
+        let script = json!({ "mode": "rewrite", "pattern": "(?i)hello", "replacement": "你好" });
+        let adapter = ScriptedAdapter::from_value(Some(&script)).unwrap();
+        assert_eq!(
+            adapter
+                .complete(&messages("Hello, world"))
+                .await
+                .unwrap()
+                .content,
+            "你好, world"
+        );
+
+        let script = json!({ "mode": "sequence", "replies": ["one", "two"] });
+        let mut conversation = messages("hi");
+        let mut replies = vec![];
+        for _ in 0..3 {
+            let adapter = ScriptedAdapter::from_value(Some(&script)).unwrap();
+            let reply = adapter.complete(&conversation).await.unwrap().content;
+            conversation.push(Message::assistant(reply.clone()));
+            conversation.push(Message::user("and?"));
+            replies.push(reply);
+        }
+        assert_eq!(replies, vec!["one", "two", "one"]);
+    }
+
+    #[tokio::test]
+    async fn scripted_adapter_should_stream_reply() {
+        let script = json!({ "mode": "fixed", "reply": "Hello there" });
+        let adapter = ScriptedAdapter::from_value(Some(&script)).unwrap();
+        let chunks: Vec<CompletionChunk> = adapter
+            .complete_stream(&messages("hi"))
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                CompletionChunk::Delta("Hello ".to_string()),
+                CompletionChunk::Delta("there".to_string()),
+                CompletionChunk::Usage(Usage {
+                    prompt_tokens: 3,
+                    completion_tokens: 2
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn scripted_adapter_should_fail_and_stall() {
+        let script = json!({ "mode": "fail", "error": "connection refused" });
+        let adapter = ScriptedAdapter::from_value(Some(&script)).unwrap();
+        let err = adapter.complete(&messages("hi")).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AiError>(),
+            Some(&AiError::Unavailable("connection refused".to_string()))
+        );
+        assert!(adapter.complete_stream(&messages("hi")).await.is_err());
+
+        let script = json!({ "mode": "stall", "reply": "Hello there" });
+        let adapter = ScriptedAdapter::from_value(Some(&script)).unwrap();
+        let mut stream = adapter.complete_stream(&messages("hi")).await.unwrap();
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(first, CompletionChunk::Delta("Hello ".to_string()));
+        stream.next().await.unwrap().unwrap();
+        let next = tokio::time::timeout(Duration::from_millis(50), stream.next()).await;
+        assert!(next.is_err(), "stream should hang after the reply");
+        let messages = messages("hi");
+        let complete = adapter.complete(&messages);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), complete)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn invalid_script_should_fail() {
+        let script = json!({ "mode": "rewrite", "pattern": "(", "replacement": "" });
+        assert!(ScriptedAdapter::from_value(Some(&script)).is_err());
+        let script = json!({ "mode": "sequence", "replies": [] });
+        assert!(ScriptedAdapter::from_value(Some(&script)).is_err());
+        let script = json!({ "mode": "shout" });
+        assert!(ScriptedAdapter::from_value(Some(&script)).is_err());
+    }
+}
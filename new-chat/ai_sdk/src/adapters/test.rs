@@ -0,0 +1,173 @@
+use crate::{
+    AiAdapter, AiService, Completion, CompletionChunk, CompletionStream, Message, Role, Tool, Usage,
+};
+use anyhow::Result;
+use futures_util::{StreamExt, stream};
+
+/// Deterministic offline adapter for tests: completions echo the last user message,
+/// embeddings hash the words of a text into a fixed size vector, so similar texts
+/// get similar vectors. Usage counts words as tokens.
+#[derive(Debug, Clone)]
+pub struct TestAdapter {
+    pub dimensions: usize,
+}
+
+impl TestAdapter {
+    pub const DEFAULT_DIMENSIONS: usize = 64;
+
+    pub fn new(dimensions: usize) -> Self {
+        Self { dimensions }
+    }
+
+    fn reply(messages: &[Message]) -> String {
+        messages
+            .iter()
+            .rev()
+            .find(|m| m.role == Role::User)
+            .map(|m| m.content.clone())
+            .unwrap_or_default()
+    }
+
+    fn embed_one(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0f32; self.dimensions.max(1)];
+        let words = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty());
+        for word in words {
+            let hash = fnv1a(word.to_lowercase().as_bytes());
+            let index = (hash % vector.len() as u64) as usize;
+            // the top bit decides the sign, to keep unrelated words from piling up
+            vector[index] += if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        }
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|v| *v /= norm);
+        }
+        vector
+    }
+}
+
+impl Default for TestAdapter {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_DIMENSIONS)
+    }
+}
+
+impl AiService for TestAdapter {
+    async fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        Ok(completion(messages, Self::reply(messages)))
+    }
+
+    async fn complete_stream(&self, messages: &[Message]) -> Result<CompletionStream> {
+        Ok(completion_stream(messages, Self::reply(messages)))
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        _tools: &[Tool],
+    ) -> Result<Completion> {
+        Ok(completion(messages, Self::reply(messages)))
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|text| self.embed_one(text)).collect())
+    }
+}
+
+/// a completion of `messages` answered by `reply`, counting words as tokens
+pub(crate) fn completion(messages: &[Message], reply: String) -> Completion {
+    let usage = Usage {
+        prompt_tokens: messages.iter().map(|m| count_words(&m.content)).sum(),
+        completion_tokens: count_words(&reply),
+    };
+    Completion {
+        content: reply,
+        tool_calls: vec![],
+        usage,
+    }
+}
+
+/// `reply` streamed word by word, then its usage
+pub(crate) fn completion_stream(messages: &[Message], reply: String) -> CompletionStream {
+    let completion = completion(messages, reply);
+    let chunks = completion
+        .content
+        .split_inclusive(' ')
+        .map(|chunk| Ok(CompletionChunk::Delta(chunk.to_string())))
+        .chain([Ok(CompletionChunk::Usage(completion.usage))])
+        .collect::<Vec<_>>();
+    stream::iter(chunks).boxed()
+}
+
+fn count_words(text: &str) -> u32 {
+    text.split_whitespace().count() as u32
+}
+
+// stable across runs and platforms, unlike std's DefaultHasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl From<TestAdapter> for AiAdapter {
+    fn from(adapter: TestAdapter) -> Self {
+        AiAdapter::Test(adapter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::TryStreamExt;
+
+    fn cosine(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| a * b).sum()
+    }
+
+    #[tokio::test]
+    async fn test_adapter_complete_should_echo() {
+        let adapter = TestAdapter::default();
+        let messages = [Message::system("be nice"), Message::user("Hello there")];
+        let completion = adapter.complete(&messages).await.unwrap();
+        assert_eq!(completion.content, "Hello there");
+        let usage = Usage {
+            prompt_tokens: 4,
+            completion_tokens: 2,
+        };
+        assert_eq!(completion.usage, usage);
+        let chunks: Vec<CompletionChunk> = adapter
+            .complete_stream(&messages)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                CompletionChunk::Delta("Hello ".to_string()),
+                CompletionChunk::Delta("there".to_string()),
+                CompletionChunk::Usage(usage),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_adapter_embed_should_be_deterministic() {
+        let adapter = TestAdapter::new(32);
+        let texts = [
+            "the weather in Tokyo".to_string(),
+            "Tokyo weather today".to_string(),
+            "quarterly revenue report".to_string(),
+            String::new(),
+        ];
+        let vectors = adapter.embed(&texts).await.unwrap();
+        assert_eq!(vectors, adapter.embed(&texts).await.unwrap());
+        assert!(vectors.iter().all(|v| v.len() == 32));
+        assert!((cosine(&vectors[0], &vectors[0]) - 1.0).abs() < 1e-5);
+        assert!(cosine(&vectors[0], &vectors[1]) > cosine(&vectors[0], &vectors[2]));
+        assert!(vectors[3].iter().all(|v| *v == 0.0));
+    }
+}
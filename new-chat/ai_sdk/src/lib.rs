@@ -2,36 +2,226 @@ mod adapters;
 
 pub use adapters::*;
 
-use std::fmt;
+use futures_util::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use std::{fmt, ops::AddAssign, time::Duration};
+use thiserror::Error;
+
+/// A stream of token deltas produced by a streaming completion, ending with
+/// the usage once the model is done.
+pub type CompletionStream = BoxStream<'static, anyhow::Result<CompletionChunk>>;
 
 pub enum AiAdapter {
     Ollama(OllamaAdapter),
+    OpenAi(OpenAiAdapter),
+    Test(TestAdapter),
+    Scripted(ScriptedAdapter),
+    Retry(RetryAdapter),
 }
 
-#[derive(Debug, Clone)]
+/// Why a request to a model server failed. Adapters return it inside their
+/// `anyhow::Error`, so callers can `downcast_ref` it to decide what to do.
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum AiError {
+    /// the server asked us to slow down, `retry_after` comes from the `Retry-After` header
+    #[error("rate limited by the model server")]
+    RateLimited { retry_after: Option<Duration> },
+
+    #[error("model server unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("request timed out")]
+    Timeout,
+
+    /// the request itself is wrong (unknown model, invalid parameters, bad key...),
+    /// sending it again won't help
+    #[error("bad request: {0}")]
+    BadRequest(String),
+}
+
+/// Generation parameters and request limits an adapter sends with every
+/// completion. Unset fields keep the model / server defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompletionOptions {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub stop: Vec<String>,
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Role {
     User,
     Assistant,
     System,
+    /// result of a tool call, see [`Message::tool`]
+    Tool,
 }
 
 #[derive(Debug, Clone)]
 pub struct Message {
     pub role: Role,
     pub content: String,
+    /// tools the assistant asked to call
+    pub tool_calls: Vec<ToolCall>,
+    /// for `Role::Tool` messages, the call this is the result of
+    pub tool_call_id: Option<String>,
+    pub tool_name: Option<String>,
+}
+
+/// A function the model may call, `parameters` is a JSON schema of its arguments
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+/// A completion that may ask for tools to be called before answering
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Completion {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+    pub usage: Usage,
+}
+
+/// Tokens a completion used, as reported by the model server (zero if it doesn't)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompletionChunk {
+    Delta(String),
+    /// sent last, when the server reports it
+    Usage(Usage),
 }
 
 #[allow(async_fn_in_trait)]
 pub trait AiService {
-    async fn complete(&self, messages: &[Message]) -> anyhow::Result<String>;
+    async fn complete(&self, messages: &[Message]) -> anyhow::Result<Completion>;
+    async fn complete_stream(&self, messages: &[Message]) -> anyhow::Result<CompletionStream>;
+    /// Like `complete`, but the model may answer with tool calls. The caller runs
+    /// them and continues the conversation with the results as `Role::Tool` messages.
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> anyhow::Result<Completion>;
+    /// Embed each text into a vector, in the same order
+    async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>>;
     // other common functions
 }
 
 // TODO: in future, use enum_dispatch crate to dispatch the methods for different adapters
 impl AiService for AiAdapter {
-    async fn complete(&self, messages: &[Message]) -> anyhow::Result<String> {
+    async fn complete(&self, messages: &[Message]) -> anyhow::Result<Completion> {
         match self {
             AiAdapter::Ollama(adapter) => adapter.complete(messages).await,
+            AiAdapter::OpenAi(adapter) => adapter.complete(messages).await,
+            AiAdapter::Test(adapter) => adapter.complete(messages).await,
+            AiAdapter::Scripted(adapter) => adapter.complete(messages).await,
+            AiAdapter::Retry(adapter) => Box::pin(adapter.complete(messages)).await,
+        }
+    }
+
+    async fn complete_stream(&self, messages: &[Message]) -> anyhow::Result<CompletionStream> {
+        match self {
+            AiAdapter::Ollama(adapter) => adapter.complete_stream(messages).await,
+            AiAdapter::OpenAi(adapter) => adapter.complete_stream(messages).await,
+            AiAdapter::Test(adapter) => adapter.complete_stream(messages).await,
+            AiAdapter::Scripted(adapter) => adapter.complete_stream(messages).await,
+            AiAdapter::Retry(adapter) => Box::pin(adapter.complete_stream(messages)).await,
+        }
+    }
+
+    async fn complete_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> anyhow::Result<Completion> {
+        match self {
+            AiAdapter::Ollama(adapter) => adapter.complete_with_tools(messages, tools).await,
+            AiAdapter::OpenAi(adapter) => adapter.complete_with_tools(messages, tools).await,
+            AiAdapter::Test(adapter) => adapter.complete_with_tools(messages, tools).await,
+            AiAdapter::Scripted(adapter) => adapter.complete_with_tools(messages, tools).await,
+            AiAdapter::Retry(adapter) => {
+                Box::pin(adapter.complete_with_tools(messages, tools)).await
+            }
+        }
+    }
+
+    async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        match self {
+            AiAdapter::Ollama(adapter) => adapter.embed(texts).await,
+            AiAdapter::OpenAi(adapter) => adapter.embed(texts).await,
+            AiAdapter::Test(adapter) => adapter.embed(texts).await,
+            AiAdapter::Scripted(adapter) => adapter.embed(texts).await,
+            AiAdapter::Retry(adapter) => Box::pin(adapter.embed(texts)).await,
+        }
+    }
+}
+
+impl AiError {
+    /// whether the same request may succeed later
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, AiError::BadRequest(_))
+    }
+
+    pub(crate) fn from_status(
+        status: reqwest::StatusCode,
+        message: String,
+        retry_after: Option<Duration>,
+    ) -> Self {
+        match status.as_u16() {
+            429 => AiError::RateLimited { retry_after },
+            408 => AiError::Timeout,
+            s if s >= 500 => AiError::Unavailable(message),
+            _ => AiError::BadRequest(message),
+        }
+    }
+}
+
+impl From<reqwest::Error> for AiError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            AiError::Timeout
+        } else if let Some(status) = e.status() {
+            AiError::from_status(status, e.to_string(), None)
+        } else {
+            AiError::Unavailable(e.to_string())
+        }
+    }
+}
+
+impl Usage {
+    pub fn total_tokens(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+impl From<String> for Completion {
+    fn from(content: String) -> Self {
+        Self {
+            content,
+            ..Default::default()
         }
     }
 }
@@ -42,6 +232,7 @@ impl fmt::Display for Role {
             Role::User => write!(f, "user"),
             Role::Assistant => write!(f, "assistant"),
             Role::System => write!(f, "system"),
+            Role::Tool => write!(f, "tool"),
         }
     }
 }
@@ -51,6 +242,9 @@ impl Message {
         Self {
             role,
             content: content.into(),
+            tool_calls: vec![],
+            tool_call_id: None,
+            tool_name: None,
         }
     }
 
@@ -65,4 +259,22 @@ impl Message {
     pub fn system(content: impl Into<String>) -> Self {
         Self::new(Role::System, content)
     }
+
+    /// the result of running `call`
+    pub fn tool(call: &ToolCall, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(call.id.clone()),
+            tool_name: Some(call.name.clone()),
+            ..Self::new(Role::Tool, content)
+        }
+    }
+}
+
+impl From<Completion> for Message {
+    fn from(completion: Completion) -> Self {
+        Self {
+            tool_calls: completion.tool_calls,
+            ..Self::assistant(completion.content)
+        }
+    }
 }
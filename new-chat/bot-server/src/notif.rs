@@ -13,7 +13,7 @@ use swiftide::{
 };
 use swiftide_pgvector::PgVectorBuilder;
 use tokio_stream::StreamExt;
-use tracing::info;
+use tracing::{info, warn};
 
 #[allow(unused)]
 #[derive(Debug)]
@@ -92,6 +92,19 @@ impl Notification {
             .pool(pool.clone())
             .vector_size(VECTOR_SIZE as _)
             .build()?;
+        // show a pending reply right away, it is finalized once the answer is ready
+        let (reply_id,): (i64,) = sqlx::query_as(
+            r#"
+          INSERT INTO messages (chat_id, sender_id, content, is_pending)
+          VALUES ($1, $2, '', TRUE)
+          RETURNING id
+          "#,
+        )
+        .bind(self.event.chat_id)
+        .bind(self.bot_id)
+        .fetch_one(pool)
+        .await?;
+
         let pipeline = query::Pipeline::default()
             .then_transform_query(query_transformers::GenerateSubquestions::from_client(
                 client.clone(),
@@ -101,21 +114,36 @@ impl Notification {
             .then_transform_response(response_transformers::Summary::from_client(client.clone()))
             .then_answer(answers::Simple::from_client(client.clone()));
         info!("Processing notification: {:?}", self.event.id);
-        let ret = pipeline.query(&self.event.content).await?;
-        let answer = ret.answer();
+        let answer = match pipeline.query(&self.event.content).await {
+            Ok(ret) => ret.answer().to_string(),
+            Err(e) => {
+                warn!("Failed to answer message {}: {}", self.event.id, e);
+                // no answer to show, the pending reply is deleted instead of left empty
+                sqlx::query(
+                    r#"
+          UPDATE messages
+          SET is_pending = FALSE, deleted_at = NOW()
+          WHERE id = $1 AND deleted_at IS NULL
+          "#,
+                )
+                .bind(reply_id)
+                .execute(pool)
+                .await?;
+                return Ok(());
+            }
+        };
         info!("Got answer. Writing to db...");
 
-        let _: (i64,) = sqlx::query_as(
+        sqlx::query(
             r#"
-          INSERT INTO messages (chat_id, sender_id, content)
-          VALUES ($1, $2, $3)
-          RETURNING id
+          UPDATE messages
+          SET content = $1, is_pending = FALSE
+          WHERE id = $2 AND deleted_at IS NULL
           "#,
         )
-        .bind(self.event.chat_id)
-        .bind(self.bot_id)
         .bind(answer)
-        .fetch_one(pool)
+        .bind(reply_id)
+        .execute(pool)
         .await?;
 
         Ok(())
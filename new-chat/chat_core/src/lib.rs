@@ -2,6 +2,7 @@ mod utils;
 
 pub mod middlewares;
 
+use ai_sdk::AiError;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
@@ -14,24 +15,55 @@ pub trait Agent {
     async fn process(&self, msg: &str, ctx: &AgentContext) -> Result<AgentDecision, AgentError>;
 }
 
+/// Everything an agent knows about the conversation a message is sent to
 #[derive(Debug, Default, Clone)]
-pub struct AgentContext {}
+pub struct AgentContext {
+    pub chat_id: i64,
+    /// not set for single chats
+    pub chat_name: Option<String>,
+    pub chat_type: ChatType,
+    pub ws_id: i64,
+    pub ws_name: String,
+    /// the user who sent the message being processed
+    pub sender: Option<User>,
+    pub members: Vec<ChatUser>,
+    /// recent messages of the chat, oldest first
+    pub history: Vec<Message>,
+}
 
-#[derive(Debug, Clone)]
+/// What an agent decided, serialized as e.g. `{"action": "reply", "content": "Hi"}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", content = "content", rename_all = "snake_case")]
 pub enum AgentDecision {
     Modify(String),
     Reply(String),
+    /// side output of a tap agent, stored or logged by its sink
+    Annotate(serde_json::Value),
+    /// don't send the message, the reason is told to the sender
+    Reject(String),
+    /// send `content`, the message with the offending spans redacted
+    Redact {
+        content: String,
+        reason: String,
+    },
+    /// send the message, but queue it for review by the workspace owners
+    Flag(String),
+    /// don't send the message, without telling why
     Delete,
     None,
 }
 
 #[derive(Error, Debug)]
 pub enum AgentError {
+    /// the model server failed, see [`AiError::is_retryable`]
     #[error("Network error: {0}")]
-    Network(String),
+    Network(#[from] AiError),
+
+    #[error("agent timed out after {0:?}")]
+    Timeout(std::time::Duration),
 
     #[error("{0}")]
-    AnyError(#[from] anyhow::Error),
+    AnyError(anyhow::Error),
 }
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
@@ -95,6 +127,18 @@ pub struct Chat {
     pub r#type: ChatType,
     pub members: Vec<i64>,
     pub agents: Vec<i64>,
+    /// the user who created the chat, may delete any message in it
+    #[sqlx(default)]
+    #[serde(default, alias = "ownerId")]
+    pub owner_id: Option<i64>,
+    /// the last message the user read, only filled in when listing chats
+    #[sqlx(default)]
+    #[serde(default, alias = "lastReadId")]
+    pub last_read_id: Option<i64>,
+    /// messages of others after `last_read_id`, only filled in when listing chats
+    #[sqlx(default)]
+    #[serde(default, alias = "unreadCount")]
+    pub unread_count: i64,
     #[serde(alias = "createdAt")]
     pub created_at: DateTime<Utc>,
 }
@@ -110,6 +154,41 @@ pub struct Message {
     pub content: String,
     pub modified_content: Option<String>,
     pub files: Vec<String>,
+    /// a pending message is still being generated by an agent
+    #[sqlx(default)]
+    #[serde(default, alias = "isPending")]
+    pub is_pending: bool,
+    /// set if the message was written by an agent, as its bot user
+    #[sqlx(default)]
+    #[serde(default, alias = "agentId")]
+    pub agent_id: Option<i64>,
+    /// the message this one quotes
+    #[sqlx(default)]
+    #[serde(default, alias = "replyToId")]
+    pub reply_to_id: Option<i64>,
+    /// the top level message of the thread this one is a reply in
+    #[sqlx(default)]
+    #[serde(default, alias = "parentId")]
+    pub parent_id: Option<i64>,
+    /// replies in the thread of this message, not counting deleted ones
+    #[sqlx(default)]
+    #[serde(default, alias = "replyCount")]
+    pub reply_count: i32,
+    #[sqlx(default)]
+    #[serde(default, alias = "lastReplyAt")]
+    pub last_reply_at: Option<DateTime<Utc>>,
+    /// last time the sender edited the message, see [`MessageEdit`]
+    #[sqlx(default)]
+    #[serde(default, alias = "editedAt")]
+    pub edited_at: Option<DateTime<Utc>>,
+    /// set for deleted messages, which are kept without content or files
+    #[sqlx(default)]
+    #[serde(default, alias = "deletedAt")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// reactions of the members, only filled in when listing messages
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
     #[serde(alias = "createdAt")]
     pub created_at: DateTime<Utc>,
 }
@@ -127,6 +206,9 @@ pub enum AgentType {
     Reply,
     #[serde(alias = "tap", alias = "Tap")]
     Tap,
+    /// checks messages before they are sent, see `moderation_reviews`
+    #[serde(alias = "moderate", alias = "Moderate")]
+    Moderate,
 }
 
 #[derive(
@@ -138,10 +220,59 @@ pub enum AdapterType {
     #[default]
     #[serde(alias = "ollama", alias = "Ollama")]
     Ollama,
+    #[sqlx(rename = "openai")]
+    #[serde(alias = "openai", alias = "OpenAi", alias = "openAi")]
+    OpenAi,
     #[serde(alias = "test", alias = "Test")]
     Test,
 }
 
+/// Adapter settings and generation parameters of an agent (`chat_agents.args`).
+/// Keys not known here are kept in `extra` for agent specific settings.
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct AgentArgs {
+    /// adapter host / endpoint without a path, e.g. `http://ollama:11434`, the adapter
+    /// adds its API path (`/v1` for OpenAI), defaults to the adapter's own
+    #[serde(default, alias = "endpoint", skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// request timeout in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// names of the server-side tools the agent may call
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<String>,
+    /// retries of a request the model server failed with a retryable error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// models of the same adapter to fall back to, in order, when the model keeps failing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<String>,
+    #[serde(flatten)]
+    #[schema(value_type = Object)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Per chat changes to the agent template a chat agent is attached to
+/// (`chat_agents.overrides`), unset fields follow the template.
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct AgentOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// set on top of the template's args, key by key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<AgentArgs>,
+}
+
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all(serialize = "camelCase"))]
 pub struct ChatAgent {
@@ -153,14 +284,170 @@ pub struct ChatAgent {
     pub adapter: AdapterType,
     pub model: String,
     pub prompt: String,
-    #[schema(value_type = Object)]
-    pub args: sqlx::types::Json<serde_json::Value>, // TODO: change to custom type
+    #[schema(value_type = AgentArgs)]
+    pub args: sqlx::types::Json<AgentArgs>,
+    /// agents of a chat run in ascending position order
+    pub position: i32,
+    /// the bot user replies are sent as, see `AppState::agent_bot_id`
+    #[sqlx(default)]
+    #[serde(default, alias = "botId")]
+    pub bot_id: Option<i64>,
+    /// the workspace agent template the agent follows, if any
+    #[sqlx(default)]
+    #[serde(default, alias = "templateId")]
+    pub template_id: Option<i64>,
+    #[sqlx(default)]
+    #[serde(default)]
+    #[schema(value_type = AgentOverrides)]
+    pub overrides: sqlx::types::Json<AgentOverrides>,
     #[serde(alias = "createdAt")]
     pub created_at: DateTime<Utc>,
     #[serde(alias = "updatedAt")]
     pub updated_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MessageAnnotation {
+    pub id: i64,
+    #[serde(alias = "messageId")]
+    pub message_id: i64,
+    #[serde(alias = "agentId")]
+    pub agent_id: Option<i64>,
+    pub kind: String,
+    #[schema(value_type = Object)]
+    pub value: sqlx::types::Json<serde_json::Value>,
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// A previous version of an edited message
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MessageEdit {
+    pub id: i64,
+    #[serde(alias = "messageId")]
+    pub message_id: i64,
+    pub content: String,
+    /// when this version was replaced
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// The members who reacted to a message with an emoji
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: i64,
+    /// in the order they reacted
+    #[serde(alias = "userIds")]
+    pub user_ids: Vec<i64>,
+}
+
+impl AgentArgs {
+    pub const MAX_STOP_SEQUENCES: usize = 4;
+    pub const MAX_TIMEOUT_SECS: u64 = 600;
+    pub const MAX_RETRIES: u32 = 5;
+    pub const MAX_FALLBACKS: usize = 3;
+
+    /// these args with the ones set in `other` on top, fails if the result isn't valid
+    /// args, e.g. an extra key of one clashing with a known one of the other
+    pub fn merge(&self, other: &AgentArgs) -> Result<AgentArgs, String> {
+        let to_object = |args: &AgentArgs| match serde_json::to_value(args) {
+            Ok(serde_json::Value::Object(args)) => Ok(args),
+            Ok(_) => Err("args must be an object".to_string()),
+            Err(e) => Err(format!("invalid args: {}", e)),
+        };
+        let mut args = to_object(self)?;
+        args.extend(to_object(other)?);
+        serde_json::from_value(serde_json::Value::Object(args))
+            .map_err(|e| format!("invalid args: {}", e))
+    }
+
+    /// check the values are in the ranges the adapters accept
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(host) = &self.host
+            && !(host.starts_with("http://") || host.starts_with("https://"))
+        {
+            return Err(format!("host must be an http(s) url: {}", host));
+        }
+        if let Some(temperature) = self.temperature
+            && !(0.0..=2.0).contains(&temperature)
+        {
+            return Err(format!(
+                "temperature must be between 0 and 2: {}",
+                temperature
+            ));
+        }
+        if let Some(top_p) = self.top_p
+            && !(0.0..=1.0).contains(&top_p)
+        {
+            return Err(format!("top_p must be between 0 and 1: {}", top_p));
+        }
+        if self.max_tokens == Some(0) {
+            return Err("max_tokens must be positive".to_string());
+        }
+        if self.stop.len() > Self::MAX_STOP_SEQUENCES {
+            return Err(format!(
+                "at most {} stop sequences are allowed",
+                Self::MAX_STOP_SEQUENCES
+            ));
+        }
+        if self.stop.iter().any(|s| s.is_empty()) {
+            return Err("stop sequences cannot be empty".to_string());
+        }
+        if let Some(timeout) = self.timeout
+            && !(1..=Self::MAX_TIMEOUT_SECS).contains(&timeout)
+        {
+            return Err(format!(
+                "timeout must be between 1 and {} seconds: {}",
+                Self::MAX_TIMEOUT_SECS,
+                timeout
+            ));
+        }
+        if let Some(retries) = self.retries
+            && retries > Self::MAX_RETRIES
+        {
+            return Err(format!(
+                "at most {} retries are allowed: {}",
+                Self::MAX_RETRIES,
+                retries
+            ));
+        }
+        if self.fallbacks.len() > Self::MAX_FALLBACKS {
+            return Err(format!(
+                "at most {} fallback models are allowed",
+                Self::MAX_FALLBACKS
+            ));
+        }
+        if self.fallbacks.iter().any(|m| m.trim().is_empty()) {
+            return Err("fallback models cannot be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl AgentError {
+    /// whether running the agent again may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AgentError::Network(e) => e.is_retryable(),
+            _ => true,
+        }
+    }
+}
+
+/// adapters report model server failures as an [`AiError`] inside `anyhow::Error`
+impl From<anyhow::Error> for AgentError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<AiError>() {
+            Ok(e) => AgentError::Network(e),
+            Err(e) => AgentError::AnyError(e),
+        }
+    }
+}
+
 impl User {
     pub fn new(id: i64, fullname: &str, email: &str) -> Self {
         Self {
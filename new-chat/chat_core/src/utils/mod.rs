@@ -1,3 +1,5 @@
 mod jwt;
+mod template;
 
 pub use jwt::{DecodingKey, EncodingKey};
+pub use template::{PromptTemplate, render_prompt};
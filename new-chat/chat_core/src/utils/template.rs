@@ -0,0 +1,204 @@
+use crate::{AgentArgs, AgentContext};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::Value;
+
+/// A prompt with `{{variable}}` placeholders, filled from the chat a message is
+/// sent to:
+/// - `sender.id`, `sender.fullname`, `sender.email`: the user who sent the message
+/// - `chat.id`, `chat.name`: the chat, the name is empty for single chats
+/// - `workspace.name`
+/// - `now`: current time in RFC 3339, UTC
+/// - `args.*`: a value of the agent's args, e.g. `{{args.language}}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptTemplate<'a> {
+    segments: Vec<Segment<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment<'a> {
+    Text(&'a str),
+    Var(&'a str),
+}
+
+impl<'a> PromptTemplate<'a> {
+    pub fn parse(prompt: &'a str) -> Result<Self, String> {
+        let mut segments = vec![];
+        let mut rest = prompt;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(&rest[..start]));
+            }
+            let Some(end) = rest[start..].find("}}") else {
+                return Err(format!("unclosed variable in prompt: {}", &rest[start..]));
+            };
+            let name = rest[start + 2..start + end].trim();
+            if name.is_empty() {
+                return Err("empty variable in prompt".to_string());
+            }
+            segments.push(Segment::Var(name));
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest));
+        }
+        Ok(Self { segments })
+    }
+
+    /// names of the variables used, in order
+    pub fn variables(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.segments.iter().filter_map(|s| match s {
+            Segment::Var(name) => Some(*name),
+            Segment::Text(_) => None,
+        })
+    }
+
+    /// check every variable is known and every `args.*` is set in `args`
+    pub fn validate(&self, args: &AgentArgs) -> Result<(), String> {
+        let ctx = AgentContext::default();
+        let vars = TemplateVars::new(args, &ctx, Utc::now());
+        match self.variables().find(|name| vars.get(name).is_none()) {
+            Some(name) => Err(format!("unknown variable in prompt: {}", name)),
+            None => Ok(()),
+        }
+    }
+
+    /// fill in the variables, unknown ones are kept as is
+    pub fn render(&self, args: &AgentArgs, ctx: &AgentContext, now: DateTime<Utc>) -> String {
+        let vars = TemplateVars::new(args, ctx, now);
+        self.segments
+            .iter()
+            .map(|s| match s {
+                Segment::Text(text) => text.to_string(),
+                Segment::Var(name) => vars
+                    .get(name)
+                    .unwrap_or_else(|| format!("{{{{{}}}}}", name)),
+            })
+            .collect()
+    }
+}
+
+/// Render `prompt` for a message, prompts saved before templating that don't
+/// parse are sent verbatim.
+pub fn render_prompt(prompt: &str, args: &AgentArgs, ctx: &AgentContext) -> String {
+    match PromptTemplate::parse(prompt) {
+        Ok(template) => template.render(args, ctx, Utc::now()),
+        Err(_) => prompt.to_string(),
+    }
+}
+
+struct TemplateVars<'a> {
+    args: Value,
+    ctx: &'a AgentContext,
+    now: DateTime<Utc>,
+}
+
+impl<'a> TemplateVars<'a> {
+    fn new(args: &AgentArgs, ctx: &'a AgentContext, now: DateTime<Utc>) -> Self {
+        Self {
+            args: serde_json::to_value(args).unwrap_or_default(),
+            ctx,
+            now,
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        let sender = self.ctx.sender.as_ref();
+        let value = match name {
+            "sender.id" => sender.map(|u| u.id.to_string()).unwrap_or_default(),
+            "sender.fullname" => sender.map(|u| u.fullname.clone()).unwrap_or_default(),
+            "sender.email" => sender.map(|u| u.email.clone()).unwrap_or_default(),
+            "chat.id" => self.ctx.chat_id.to_string(),
+            "chat.name" => self.ctx.chat_name.clone().unwrap_or_default(),
+            "workspace.name" => self.ctx.ws_name.clone(),
+            "now" => self.now.to_rfc3339_opts(SecondsFormat::Secs, true),
+            _ => {
+                let path = name.strip_prefix("args.")?;
+                let value = path
+                    .split('.')
+                    .try_fold(&self.args, |value, key| value.get(key))?;
+                match value {
+                    Value::String(s) => s.clone(),
+                    Value::Null => String::new(),
+                    value => value.to_string(),
+                }
+            }
+        };
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::User;
+    use chrono::TimeZone;
+
+    fn args() -> AgentArgs {
+        serde_json::from_value(serde_json::json!({
+            "language": "French",
+            "style": { "tone": "formal" },
+            "temperature": 0.5
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn prompt_template_should_render_variables() {
+        let ctx = AgentContext {
+            chat_id: 2,
+            chat_name: Some("project".to_string()),
+            ws_name: "acme".to_string(),
+            sender: Some(User::new(1, "Alice", "alice@acme.org")),
+            ..Default::default()
+        };
+        let now = Utc.with_ymd_and_hms(2026, 10, 18, 9, 30, 0).unwrap();
+        let template = PromptTemplate::parse(
+            "Hi {{ sender.fullname }} in #{{chat.name}} ({{workspace.name}}) at {{now}}: \
+             answer in {{args.language}}, {{args.style.tone}}, {{args.temperature}}",
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&args(), &ctx, now),
+            "Hi Alice in #project (acme) at 2026-10-18T09:30:00Z: answer in French, formal, 0.5"
+        );
+    }
+
+    #[test]
+    fn prompt_template_should_validate_variables() {
+        let args = args();
+        assert!(
+            PromptTemplate::parse("plain prompt")
+                .unwrap()
+                .validate(&args)
+                .is_ok()
+        );
+        assert!(
+            PromptTemplate::parse("{{sender.email}} {{args.style.tone}}")
+                .unwrap()
+                .validate(&args)
+                .is_ok()
+        );
+
+        let err = PromptTemplate::parse("{{sender.age}}")
+            .unwrap()
+            .validate(&args)
+            .unwrap_err();
+        assert_eq!(err, "unknown variable in prompt: sender.age");
+        let err = PromptTemplate::parse("{{args.missing}}")
+            .unwrap()
+            .validate(&args)
+            .unwrap_err();
+        assert_eq!(err, "unknown variable in prompt: args.missing");
+        assert!(PromptTemplate::parse("oops {{chat.name").is_err());
+        assert!(PromptTemplate::parse("{{ }}").is_err());
+    }
+
+    #[test]
+    fn render_prompt_should_keep_unparsable_prompts() {
+        let ctx = AgentContext::default();
+        assert_eq!(
+            render_prompt("reply with {{ only", &args(), &ctx),
+            "reply with {{ only"
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::{Result, bail};
 use serde::{Deserialize, Serialize};
-use std::{env, fs::File, path::PathBuf};
+use std::{collections::HashMap, env, fs::File, path::PathBuf};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
@@ -10,6 +10,16 @@ pub struct AppConfig {
     pub redis: Option<RedisConfig>,
     #[serde(default)]
     pub rate_limit: Option<RateLimitConfig>,
+    #[serde(default)]
+    pub agent_worker: AgentWorkerConfig,
+    /// AI quotas of the workspaces, counted in redis, unlimited when not set. Quotas
+    /// fail open: agents keep running while redis is unavailable.
+    #[serde(default)]
+    pub ai_quota: Option<AiQuotaConfig>,
+    /// hosts agents may set in `args.host`, e.g. `http://ollama:11434`, the server's
+    /// API keys are sent to them; without it agents use their adapter's default
+    #[serde(default)]
+    pub agent_hosts: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -69,6 +79,74 @@ fn default_window_secs() -> u64 {
     60
 }
 
+/// Background worker running the chat agents of newly sent messages
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AgentWorkerConfig {
+    /// how often to poll the queue when it's empty
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// timeout of a single agent call
+    #[serde(default = "default_agent_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: i32,
+    /// a run still `running` after this long is considered abandoned and claimed again
+    #[serde(default = "default_lease_secs")]
+    pub lease_secs: u64,
+}
+
+impl Default for AgentWorkerConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: default_poll_interval_ms(),
+            timeout_secs: default_agent_timeout_secs(),
+            max_attempts: default_max_attempts(),
+            lease_secs: default_lease_secs(),
+        }
+    }
+}
+
+fn default_poll_interval_ms() -> u64 {
+    500
+}
+
+fn default_agent_timeout_secs() -> u64 {
+    60
+}
+
+fn default_max_attempts() -> i32 {
+    3
+}
+
+fn default_lease_secs() -> u64 {
+    600
+}
+
+/// Limits of the agent calls of a workspace, unset ones are unlimited
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct AiQuotaLimits {
+    #[serde(default)]
+    pub requests_per_day: Option<u64>,
+    #[serde(default)]
+    pub tokens_per_month: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AiQuotaConfig {
+    /// limits of every workspace
+    #[serde(flatten)]
+    pub default: AiQuotaLimits,
+    /// limits of specific workspaces by id, replacing the default ones
+    #[serde(default)]
+    pub workspaces: HashMap<i64, AiQuotaLimits>,
+}
+
+impl AiQuotaConfig {
+    pub fn limits(&self, ws_id: i64) -> AiQuotaLimits {
+        self.workspaces.get(&ws_id).copied().unwrap_or(self.default)
+    }
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self> {
         // read from ./app.yaml, /etc/config/app.yaml, or from env CHAT_CONFIG
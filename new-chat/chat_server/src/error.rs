@@ -1,3 +1,4 @@
+use ai_sdk::AiError;
 use axum::{Json, http::StatusCode, response::IntoResponse};
 use chat_core::AgentError;
 use serde::{Deserialize, Serialize};
@@ -7,6 +8,22 @@ use utoipa::ToSchema;
 #[derive(Debug, Serialize, ToSchema, Deserialize)]
 pub struct ErrorOutput {
     pub error: String,
+    /// set when a moderation agent rejected the message being sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejection: Option<MessageRejection>,
+}
+
+/// Why a message wasn't sent
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct MessageRejection {
+    /// name of the moderation agent
+    pub agent: String,
+    /// empty if the agent didn't give one
+    pub reason: String,
+    /// the entry in the workspace's moderation review queue
+    #[serde(alias = "reviewId")]
+    pub review_id: i64,
 }
 
 #[derive(Debug, Error)]
@@ -32,12 +49,27 @@ pub enum AppError {
     #[error("delete agent error: {0}")]
     DeleteAgentError(String),
 
+    #[error("test agent error: {0}")]
+    TestAgentError(String),
+
     #[error("create message error: {0}")]
     CreateMessageError(String),
 
+    #[error("update message error: {0}")]
+    UpdateMessageError(String),
+
     #[error("delete message error: {0}")]
     DeleteMessageError(String),
 
+    #[error("reaction error: {0}")]
+    ReactionError(String),
+
+    #[error("message rejected by {}: {}", .0.agent, .0.reason)]
+    MessageRejected(MessageRejection),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
     #[error("not logged in")]
     NotLoggedInError,
 
@@ -62,9 +94,15 @@ pub enum AppError {
     #[error("ai agent error: {0}")]
     AiAgentError(#[from] AgentError),
 
+    #[error("tool error: {0}")]
+    ToolError(String),
+
     #[error("rate limit exceeded: {0}")]
     RateLimitExceeded(String),
 
+    #[error("ai quota exceeded: {0}")]
+    AiQuotaExceeded(String),
+
     #[error("redis error: {0}")]
     RedisError(#[from] deadpool_redis::PoolError),
 
@@ -77,7 +115,10 @@ pub enum AppError {
 
 impl ErrorOutput {
     pub fn new(error: String) -> Self {
-        Self { error }
+        Self {
+            error,
+            rejection: None,
+        }
     }
 }
 
@@ -91,22 +132,40 @@ impl IntoResponse for AppError {
             | Self::CreateAgentError(_)
             | Self::UpdateAgentError(_)
             | Self::DeleteAgentError(_)
-            | Self::DeleteMessageError(_) => StatusCode::BAD_REQUEST,
-            Self::NotChatMemberError { .. } => StatusCode::FORBIDDEN,
+            | Self::TestAgentError(_)
+            | Self::UpdateMessageError(_)
+            | Self::DeleteMessageError(_)
+            | Self::ReactionError(_)
+            | Self::ToolError(_) => StatusCode::BAD_REQUEST,
+            Self::MessageRejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::NotChatMemberError { .. } | Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Argon2Error(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::AnyError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::HttpHeaderError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::AiAgentError(AgentError::Network(AiError::RateLimited { .. })) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::AiAgentError(AgentError::Network(AiError::Unavailable(_))) => {
+                StatusCode::BAD_GATEWAY
+            }
+            Self::AiAgentError(AgentError::Network(AiError::Timeout) | AgentError::Timeout(_)) => {
+                StatusCode::GATEWAY_TIMEOUT
+            }
             Self::AiAgentError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::NotLoggedInError => StatusCode::UNAUTHORIZED,
-            Self::RateLimitExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::RateLimitExceeded(_) | Self::AiQuotaExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
             Self::RedisError(_) | Self::RedisBuildError(_) | Self::AxumError(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
         };
 
-        (status, Json(ErrorOutput::new(self.to_string()))).into_response()
+        let mut output = ErrorOutput::new(self.to_string());
+        if let Self::MessageRejected(rejection) = self {
+            output.rejection = Some(rejection);
+        }
+        (status, Json(output)).into_response()
     }
 }
@@ -1,15 +1,18 @@
 use crate::{
     AppError, AppState,
     error::ErrorOutput,
-    models::{CreateAgent, UpdateAgent},
+    models::{
+        AgentTemplate, AgentTemplateVersion, AgentTestOutput, AttachAgent, CreateAgent,
+        CreateAgentTemplate, TestAgent, TestAgentDefinition, UpdateAgent, UpdateAgentTemplate,
+    },
 };
 use axum::{
-    Json,
+    Extension, Json,
     extract::{Path, State},
     http::StatusCode,
     response::IntoResponse,
 };
-use chat_core::ChatAgent;
+use chat_core::{ChatAgent, User};
 
 /// List all agents in the chat
 #[utoipa::path(
@@ -56,6 +59,31 @@ pub(crate) async fn create_agent_handler(
     Ok((StatusCode::OK, Json(agent)).into_response())
 }
 
+/// Add an agent following a template of the workspace to the chat
+#[utoipa::path(
+    post,
+    path = "/api/chats/{chat_id}/agents/attach",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Agent attached", body = ChatAgent),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 404, description = "Agent template not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn attach_agent_handler(
+    Path(chat_id): Path<u64>,
+    State(state): State<AppState>,
+    Json(input): Json<AttachAgent>,
+) -> Result<impl IntoResponse, AppError> {
+    let agent = state.attach_agent_template(input, chat_id as _).await?;
+    Ok((StatusCode::OK, Json(agent)).into_response())
+}
+
 /// Update the agent by id
 #[utoipa::path(
     patch,
@@ -103,3 +131,160 @@ pub(crate) async fn delete_agent_handler(
     state.delete_agent(chat_id, agent_id).await?;
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// Run an agent of the chat on a sample message, without sending anything
+#[utoipa::path(
+    post,
+    path = "/api/chats/{chat_id}/agents/{agent_id}/test",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id"),
+        ("agent_id" = u64, Path, description = "Agent id")
+    ),
+    responses(
+        (status = 200, description = "What the agent would do", body = AgentTestOutput),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 404, description = "Agent not found", body = ErrorOutput),
+        (status = 429, description = "AI quota exceeded", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn test_agent_handler(
+    Extension(user): Extension<User>,
+    Path((chat_id, agent_id)): Path<(u64, u64)>,
+    State(state): State<AppState>,
+    Json(input): Json<TestAgent>,
+) -> Result<impl IntoResponse, AppError> {
+    let output = state
+        .test_agent(input, chat_id, agent_id, user.id as _)
+        .await?;
+    Ok((StatusCode::OK, Json(output)).into_response())
+}
+
+/// Run an unsaved agent definition on a sample message, without sending anything
+#[utoipa::path(
+    post,
+    path = "/api/chats/{chat_id}/agents/test",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "What the agent would do", body = AgentTestOutput),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 429, description = "AI quota exceeded", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn test_agent_definition_handler(
+    Extension(user): Extension<User>,
+    Path(chat_id): Path<u64>,
+    State(state): State<AppState>,
+    Json(input): Json<TestAgentDefinition>,
+) -> Result<impl IntoResponse, AppError> {
+    let output = state
+        .test_agent_definition(input, chat_id, user.id as _)
+        .await?;
+    Ok((StatusCode::OK, Json(output)).into_response())
+}
+
+/// List the agent templates of the workspace
+#[utoipa::path(
+    get,
+    path = "/api/workspaces/agents",
+    responses(
+        (status = 200, description = "List of agent templates", body = Vec<AgentTemplate>)
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn list_agent_template_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let templates = state.list_agent_templates(user.ws_id as _).await?;
+    Ok((StatusCode::OK, Json(templates)).into_response())
+}
+
+/// Create an agent template in the workspace, as its owner
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/agents",
+    responses(
+        (status = 201, description = "Agent template created", body = AgentTemplate),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn create_agent_template_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateAgentTemplate>,
+) -> Result<impl IntoResponse, AppError> {
+    let template = state
+        .create_agent_template(input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(template)).into_response())
+}
+
+/// Update an agent template, as a new version applied to the chats it is attached to,
+/// as the workspace owner
+#[utoipa::path(
+    patch,
+    path = "/api/workspaces/agents/{id}",
+    params(
+        ("id" = u64, Path, description = "Agent template id")
+    ),
+    responses(
+        (status = 200, description = "Agent template updated", body = AgentTemplate),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Agent template not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn update_agent_template_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateAgentTemplate>,
+) -> Result<impl IntoResponse, AppError> {
+    let template = state
+        .update_agent_template(input, user.ws_id as _, id, user.id as _)
+        .await?;
+    Ok((StatusCode::OK, Json(template)).into_response())
+}
+
+/// List the versions of an agent template, latest first
+#[utoipa::path(
+    get,
+    path = "/api/workspaces/agents/{id}/versions",
+    params(
+        ("id" = u64, Path, description = "Agent template id")
+    ),
+    responses(
+        (status = 200, description = "Versions of the agent template", body = Vec<AgentTemplateVersion>),
+        (status = 404, description = "Agent template not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn list_agent_template_versions_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let versions = state
+        .list_agent_template_versions(user.ws_id as _, id)
+        .await?;
+    Ok((StatusCode::OK, Json(versions)).into_response())
+}
@@ -1,7 +1,7 @@
 use crate::{
     AppError, AppState,
     error::ErrorOutput,
-    models::{AddMembers, CreateChat, UpdateChat},
+    models::{AddMembers, CreateChat, MarkRead, ReadReceipt, UpdateChat},
 };
 use axum::{
     Extension, Json,
@@ -11,7 +11,7 @@ use axum::{
 };
 use chat_core::{Chat, User};
 
-/// List all chats in the workspace of the user
+/// List all chats in the workspace of the user, with their unread messages
 #[utoipa::path(
     get,
     path = "/api/chats",
@@ -30,6 +30,32 @@ pub(crate) async fn list_chat_handler(
     Ok((StatusCode::OK, Json(chats)).into_response())
 }
 
+/// Mark the chat read up to a message, the latest one by default
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/read",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    request_body = MarkRead,
+    responses(
+        (status = 200, description = "Last read message of the user", body = ReadReceipt),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn mark_chat_read_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<MarkRead>,
+) -> Result<impl IntoResponse, AppError> {
+    let receipt = state.mark_chat_read(input, id, user.id as _).await?;
+    Ok(Json(receipt))
+}
+
 /// Get the chat info by id
 #[utoipa::path(
     get,
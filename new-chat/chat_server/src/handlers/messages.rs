@@ -1,18 +1,21 @@
 use crate::{
     AppError, AppState,
     error::ErrorOutput,
-    models::{ChatFile, CreateMessage, ListMessages},
+    models::{ChatFile, CreateMessage, ListMessages, Thread, UpdateMessage},
 };
 use axum::{
     Extension, Json,
     extract::{Multipart, Path, Query, State},
-    http::{HeaderMap, StatusCode},
+    http::{HeaderMap, HeaderValue, StatusCode},
     response::IntoResponse,
 };
-use chat_core::{Message, User};
+use chat_core::{Message, MessageAnnotation, MessageEdit, Reaction, User};
 use tokio::fs;
 use tracing::{info, warn};
 
+/// set on sent messages whose agents didn't run, to the reason why
+pub(crate) const AGENTS_SKIPPED_HEADER: &str = "x-agents-skipped";
+
 /// Send a new message in the chat.
 #[utoipa::path(
     post,
@@ -21,8 +24,11 @@ use tracing::{info, warn};
         ("id" = u64, Path, description = "Chat id")
     ),
     responses(
-        (status = 200, description = "List of messages", body = Message),
+        (status = 201, description = "The message sent", body = Message, headers(
+            ("x-agents-skipped" = String, description = "Why the agents of the chat didn't run, e.g. the AI quota is used up")
+        )),
         (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 422, description = "Rejected by a moderation agent", body = ErrorOutput),
     ),
     security(
         ("token" = [])
@@ -34,9 +40,13 @@ pub(crate) async fn send_message_handler(
     Path(id): Path<u64>,
     Json(input): Json<CreateMessage>,
 ) -> Result<impl IntoResponse, AppError> {
-    let msg = state.create_message(input, id, user.id as _).await?;
+    let (msg, agents_skipped) = state.send_message(input, id, user.id as _).await?;
 
-    Ok((StatusCode::CREATED, Json(msg)))
+    let mut headers = HeaderMap::new();
+    if let Some(reason) = agents_skipped.and_then(|r| HeaderValue::from_str(&r).ok()) {
+        headers.insert(AGENTS_SKIPPED_HEADER, reason);
+    }
+    Ok((StatusCode::CREATED, headers, Json(msg)))
 }
 
 /// List all messages in the chat.
@@ -65,7 +75,87 @@ pub(crate) async fn list_message_handler(
     Ok(Json(messages))
 }
 
-/// Delete a message in the chat.
+/// List the replies in the thread of a message.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{chat_id}/messages/{message_id}/thread",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id"),
+        ("message_id" = u64, Path, description = "Message id"),
+        ListMessages
+    ),
+    responses(
+        (status = 200, description = "The message with its replies", body = Thread),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_thread_handler(
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let thread = state.list_thread(input, chat_id, message_id).await?;
+    Ok(Json(thread))
+}
+
+/// Edit a message the user sent in the chat.
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{chat_id}/messages/{message_id}",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id"),
+        ("message_id" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 200, description = "Message edited", body = Message),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+        (status = 422, description = "Rejected by a moderation agent", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+    Json(input): Json<UpdateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let message = state
+        .update_message(input, chat_id, message_id, user.id as _)
+        .await?;
+    Ok(Json(message))
+}
+
+/// List the previous versions of an edited message.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{chat_id}/messages/{message_id}/edits",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id"),
+        ("message_id" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 200, description = "Previous versions, oldest first", body = Vec<MessageEdit>),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_message_edits_handler(
+    State(state): State<AppState>,
+    Path((chat_id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let edits = state.list_message_edits(chat_id, message_id).await?;
+    Ok(Json(edits))
+}
+
+/// Delete a message in the chat, as its sender or an owner of the chat or workspace.
 #[utoipa::path(
     delete,
     path = "/api/chats/{chat_id}/messages/{message_id}",
@@ -93,6 +183,87 @@ pub(crate) async fn delete_message_handler(
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// React to a message with an emoji, reacting again with the same one does nothing.
+#[utoipa::path(
+    put,
+    path = "/api/chats/{chat_id}/messages/{message_id}/reactions/{emoji}",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id"),
+        ("message_id" = u64, Path, description = "Message id"),
+        ("emoji" = String, Path, description = "Emoji, percent-encoded")
+    ),
+    responses(
+        (status = 200, description = "Reactions to the message", body = Vec<Reaction>),
+        (status = 400, description = "Invalid emoji", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id, emoji)): Path<(u64, u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state
+        .add_reaction(chat_id, message_id, user.id as _, &emoji)
+        .await?;
+    Ok(Json(reactions))
+}
+
+/// Take back a reaction to a message.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{chat_id}/messages/{message_id}/reactions/{emoji}",
+    params(
+        ("chat_id" = u64, Path, description = "Chat id"),
+        ("message_id" = u64, Path, description = "Message id"),
+        ("emoji" = String, Path, description = "Emoji, percent-encoded")
+    ),
+    responses(
+        (status = 200, description = "Reactions left on the message", body = Vec<Reaction>),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn remove_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((chat_id, message_id, emoji)): Path<(u64, u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state
+        .remove_reaction(chat_id, message_id, user.id as _, &emoji)
+        .await?;
+    Ok(Json(reactions))
+}
+
+/// List the annotations tap agents produced for a message.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{message_id}/annotations",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("message_id" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 200, description = "List of annotations", body = Vec<MessageAnnotation>),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_annotation_handler(
+    State(state): State<AppState>,
+    Path((id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let annotations = state.list_annotations(id, message_id).await?;
+    Ok(Json(annotations))
+}
+
 pub(crate) async fn file_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
@@ -1,9 +1,17 @@
 use crate::{
     AppError, AppState,
     error::ErrorOutput,
-    models::{CreateInvitation, JoinWorkspace, WorkspaceInvitation},
+    models::{
+        CreateInvitation, JoinWorkspace, ModerationReview, ResolveModerationReview, UsageQuery,
+        WorkspaceInvitation, WorkspaceUsage,
+    },
+};
+use axum::{
+    Extension, Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
 };
-use axum::{Extension, Json, extract::State, http::StatusCode, response::IntoResponse};
 use chat_core::{ChatUser, User};
 
 #[utoipa::path(
@@ -25,6 +33,27 @@ pub(crate) async fn list_chat_users_handler(
     Ok((StatusCode::OK, Json(users)).into_response())
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/workspaces/usage",
+    params(UsageQuery),
+    responses(
+        (status = 200, description = "Agent usage of the workspace", body = WorkspaceUsage),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn get_workspace_usage_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<UsageQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let usage = state.get_workspace_usage(user.ws_id as _, &input).await?;
+    Ok((StatusCode::OK, Json(usage)).into_response())
+}
+
 #[utoipa::path(
     post,
     path = "/api/workspaces/invitations",
@@ -130,3 +159,53 @@ pub(crate) async fn deactivate_invitation_handler(
     )
         .into_response())
 }
+
+/// List the messages moderation agents acted on that are waiting for review
+#[utoipa::path(
+    get,
+    path = "/api/workspaces/moderation",
+    responses(
+        (status = 200, description = "Unresolved moderation reviews", body = Vec<ModerationReview>),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn list_moderation_reviews_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let reviews = state
+        .list_moderation_reviews(user.ws_id as _, user.id as _)
+        .await?;
+    Ok((StatusCode::OK, Json(reviews)).into_response())
+}
+
+/// Resolve a moderation review, optionally deleting its message
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/moderation/{id}/resolve",
+    params(
+        ("id" = u64, Path, description = "Moderation review id")
+    ),
+    responses(
+        (status = 200, description = "Moderation review resolved", body = ModerationReview),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Unresolved moderation review not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn resolve_moderation_review_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<u64>,
+    Json(input): Json<ResolveModerationReview>,
+) -> Result<impl IntoResponse, AppError> {
+    let review = state
+        .resolve_moderation_review(input, user.ws_id as _, id, user.id as _)
+        .await?;
+    Ok((StatusCode::OK, Json(review)).into_response())
+}
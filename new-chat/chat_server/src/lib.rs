@@ -5,7 +5,10 @@ mod handlers;
 mod middlewares;
 mod models;
 mod openapi;
+mod quota;
 mod redis;
+mod tools;
+mod worker;
 
 use crate::{
     config::SigninRateLimit,
@@ -17,7 +20,7 @@ use crate::{
 use anyhow::Context;
 use axum::{
     Router,
-    http::Method,
+    http::{HeaderName, Method},
     middleware::from_fn_with_state,
     routing::{get, post},
 };
@@ -56,7 +59,6 @@ pub struct AppStateInner {
     pub(crate) ek: EncodingKey,
     pub(crate) dk: DecodingKey,
     pub(crate) pool: PgPool,
-    #[allow(dead_code)]
     pub(crate) redis: Option<RedisPool>,
     pub(crate) rate_limit_state: Option<RateLimitState>,
 }
@@ -76,15 +78,35 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
                 .post(create_agent_handler)
                 .patch(update_agent_handler),
         )
+        .route("/{id}/agents/attach", post(attach_agent_handler))
+        .route("/{id}/agents/test", post(test_agent_definition_handler))
         .route(
             "/{id}/agents/{agent_id}",
             axum::routing::delete(delete_agent_handler),
         )
+        .route("/{id}/agents/{agent_id}/test", post(test_agent_handler))
         .route("/{id}/messages", get(list_message_handler))
         .route(
             "/{id}/messages/{message_id}",
-            axum::routing::delete(delete_message_handler),
+            axum::routing::delete(delete_message_handler).patch(update_message_handler),
         )
+        .route(
+            "/{id}/messages/{message_id}/edits",
+            get(list_message_edits_handler),
+        )
+        .route(
+            "/{id}/messages/{message_id}/thread",
+            get(list_thread_handler),
+        )
+        .route(
+            "/{id}/messages/{message_id}/reactions/{emoji}",
+            axum::routing::put(add_reaction_handler).delete(remove_reaction_handler),
+        )
+        .route(
+            "/{id}/messages/{message_id}/annotations",
+            get(list_annotation_handler),
+        )
+        .route("/{id}/read", post(mark_chat_read_handler))
         .route("/{id}/members", post(add_members_handler))
         .route(
             "/{id}/members/{member_id}",
@@ -102,6 +124,7 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
             Method::PUT,
         ])
         .allow_headers(Any)
+        .expose_headers([HeaderName::from_static(AGENTS_SKIPPED_HEADER)])
         .allow_origin(Any);
 
     // Build the signin route with optional rate limiting
@@ -127,6 +150,27 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
             axum::routing::delete(deactivate_invitation_handler),
         )
         .route("/workspaces/join", post(join_workspace_handler))
+        .route("/workspaces/usage", get(get_workspace_usage_handler))
+        .route(
+            "/workspaces/agents",
+            get(list_agent_template_handler).post(create_agent_template_handler),
+        )
+        .route(
+            "/workspaces/agents/{id}",
+            axum::routing::patch(update_agent_template_handler),
+        )
+        .route(
+            "/workspaces/agents/{id}/versions",
+            get(list_agent_template_versions_handler),
+        )
+        .route(
+            "/workspaces/moderation",
+            get(list_moderation_reviews_handler),
+        )
+        .route(
+            "/workspaces/moderation/{id}/resolve",
+            post(resolve_moderation_review_handler),
+        )
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         // routes doesn't need token verification
         .route("/signin", signin_route)
@@ -246,6 +290,13 @@ mod test_util {
             };
             Ok((tdb, state))
         }
+
+        /// this state with its config changed, e.g. a shorter agent timeout
+        pub fn with_config(mut self, f: impl FnOnce(&mut AppConfig)) -> Self {
+            let inner = Arc::get_mut(&mut self.inner).expect("state shouldn't be shared yet");
+            f(&mut inner.config);
+            self
+        }
     }
 
     pub async fn get_test_pool(url: Option<&str>) -> (TestPg, PgPool) {
@@ -14,6 +14,7 @@ async fn main() -> Result<()> {
     let addr = format!("0.0.0.0:{}", port);
 
     let state = AppState::try_new(config).await?;
+    state.spawn_agent_worker();
     let app = get_router(state).await?;
     let listener = TcpListener::bind(&addr).await?;
     info!("Listening on: {}", addr);
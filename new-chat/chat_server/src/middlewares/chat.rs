@@ -5,22 +5,20 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use chat_core::User;
+use std::collections::HashMap;
 use tracing::warn;
 
 pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next) -> Response {
     // verify if user_id is a member of chat_id
     let (mut parts, body) = req.into_parts();
 
-    // Extract chat_id from path - handle both single and multi-parameter routes
-    let chat_id = if let Ok(Path((id, _))) =
-        Path::<(u64, u64)>::from_request_parts(&mut parts, &state).await
-    {
-        // Route with two parameters like /{id}/members/{member_id}
-        id
-    } else if let Ok(Path(id)) = Path::<u64>::from_request_parts(&mut parts, &state).await {
-        // Route with single parameter like /{id}
-        id
-    } else {
+    // the chat id is the `id` param, whatever other params the route has, e.g.
+    // /{id}/messages/{message_id}/reactions/{emoji}
+    let params = Path::<HashMap<String, String>>::from_request_parts(&mut parts, &state).await;
+    let Some(chat_id) = params
+        .ok()
+        .and_then(|Path(params)| params.get("id")?.parse::<u64>().ok())
+    else {
         warn!("failed to extract chat_id from path");
         return AppError::NotFound("invalid chat path".to_string()).into_response();
     };
@@ -94,4 +92,37 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn verify_chat_should_allow_reaction_routes() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state
+            .find_user_by_id(2)
+            .await?
+            .expect("user id: 2 should exists");
+        let token = state.ek.sign_access(user)?;
+        let app = crate::get_router(state).await?;
+
+        // 👍, percent-encoded
+        let uri = "/api/chats/1/messages/1/reactions/%F0%9F%91%8D";
+        for method in ["PUT", "DELETE"] {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(StatusCode::OK, res.status(), "{} {}", method, uri);
+        }
+
+        // user 2 isn't in chat 4
+        let req = Request::builder()
+            .method("PUT")
+            .uri("/api/chats/4/messages/1/reactions/%F0%9F%91%8D")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+        Ok(())
+    }
 }
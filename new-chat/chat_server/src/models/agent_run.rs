@@ -0,0 +1,168 @@
+use crate::{AppError, AppState};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "agent_run_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AgentRunStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// A run of a chat's agent pipeline on one message, queued by `create_message`
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct AgentRun {
+    pub id: i64,
+    pub message_id: i64,
+    pub chat_id: i64,
+    pub status: AgentRunStatus,
+    pub attempts: i32,
+    pub error: Option<String>,
+    /// agents `@mentioned` by the message, only mentioned reply agents answer
+    #[sqlx(default)]
+    pub mentions: Vec<i64>,
+    pub run_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// Claim the oldest due run for this worker. Runs left `running` longer than
+    /// `lease` (e.g. the worker crashed) are claimed again.
+    pub async fn claim_agent_run(&self, lease: Duration) -> Result<Option<AgentRun>, AppError> {
+        let run = sqlx::query_as(
+            r#"
+            UPDATE agent_runs
+            SET status = 'running', attempts = attempts + 1, started_at = NOW()
+            WHERE id = (
+                SELECT id FROM agent_runs
+                WHERE (status = 'pending' AND run_at <= NOW())
+                OR (status = 'running' AND started_at < NOW() - make_interval(secs => $1))
+                ORDER BY id
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(lease.as_secs_f64())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(run)
+    }
+
+    /// put a failed run back in the queue, to be picked up again after `delay`
+    pub async fn retry_agent_run(
+        &self,
+        id: i64,
+        error: &str,
+        delay: Duration,
+    ) -> Result<AgentRun, AppError> {
+        let run = sqlx::query_as(
+            r#"
+            UPDATE agent_runs
+            SET status = 'pending', error = $2, run_at = NOW() + make_interval(secs => $3)
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(error)
+        .bind(delay.as_secs_f64())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(run)
+    }
+
+    /// mark a run as done, `error` tells what went wrong if it failed
+    pub async fn finish_agent_run(
+        &self,
+        id: i64,
+        status: AgentRunStatus,
+        error: Option<&str>,
+    ) -> Result<AgentRun, AppError> {
+        let run = sqlx::query_as(
+            r#"
+            UPDATE agent_runs
+            SET status = $2, error = $3, finished_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(status)
+        .bind(error)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(run)
+    }
+
+    /// list the agent runs of a message, oldest first
+    pub async fn list_agent_runs(&self, message_id: u64) -> Result<Vec<AgentRun>, AppError> {
+        let runs = sqlx::query_as(
+            r#"
+            SELECT * FROM agent_runs
+            WHERE message_id = $1
+            ORDER BY id ASC
+            "#,
+        )
+        .bind(message_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(runs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn agent_run_queue_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query("INSERT INTO agent_runs (message_id, chat_id) VALUES (1, 1), (2, 1)")
+            .execute(&state.pool)
+            .await?;
+        let lease = Duration::from_secs(60);
+
+        let run = state
+            .claim_agent_run(lease)
+            .await?
+            .expect("run should exist");
+        assert_eq!(run.message_id, 1);
+        assert_eq!(run.status, AgentRunStatus::Running);
+        assert_eq!(run.attempts, 1);
+
+        // retried runs aren't due before the delay
+        let run = state
+            .retry_agent_run(run.id, "boom", Duration::from_secs(60))
+            .await?;
+        assert_eq!(run.status, AgentRunStatus::Pending);
+        assert_eq!(run.error.as_deref(), Some("boom"));
+
+        let run = state
+            .claim_agent_run(lease)
+            .await?
+            .expect("run should exist");
+        assert_eq!(run.message_id, 2);
+        let run = state
+            .finish_agent_run(run.id, AgentRunStatus::Succeeded, None)
+            .await?;
+        assert_eq!(run.status, AgentRunStatus::Succeeded);
+        assert!(run.finished_at.is_some());
+
+        assert!(state.claim_agent_run(lease).await?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,696 @@
+use super::agent::{validate_args, validate_prompt};
+use crate::{AppError, AppState};
+use chat_core::{AdapterType, AgentArgs, AgentOverrides, AgentType, ChatAgent};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, types::Json};
+use tracing::info;
+use utoipa::ToSchema;
+
+/// A workspace agent definition, attached to chats as chat agents which follow
+/// its updates.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct AgentTemplate {
+    pub id: i64,
+    pub ws_id: i64,
+    pub name: String,
+    pub r#type: AgentType,
+    pub adapter: AdapterType,
+    pub model: String,
+    pub prompt: String,
+    #[schema(value_type = AgentArgs)]
+    pub args: Json<AgentArgs>,
+    /// bumped by every update, see [`AgentTemplateVersion`]
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A definition an agent template had
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct AgentTemplateVersion {
+    pub id: i64,
+    pub template_id: i64,
+    pub version: i32,
+    pub model: String,
+    pub prompt: String,
+    #[schema(value_type = AgentArgs)]
+    pub args: Json<AgentArgs>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct CreateAgentTemplate {
+    pub name: String,
+    pub r#type: AgentType,
+    pub adapter: AdapterType,
+    pub model: String,
+    pub prompt: String,
+    #[serde(default)]
+    pub args: AgentArgs,
+}
+
+/// unset fields keep their current value, args are replaced as a whole
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct UpdateAgentTemplate {
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub prompt: Option<String>,
+    #[serde(default)]
+    pub args: Option<AgentArgs>,
+}
+
+/// attach a template of the chat's workspace to the chat
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct AttachAgent {
+    pub template_id: u64,
+    #[serde(default)]
+    pub overrides: AgentOverrides,
+    /// position in the chat's agent pipeline, appended to the end if not set
+    #[serde(default)]
+    pub position: Option<i32>,
+}
+
+impl AgentTemplate {
+    /// the model, prompt and args of a chat agent attached with `overrides`, fails if
+    /// the args don't merge
+    pub fn apply(&self, overrides: &AgentOverrides) -> Result<(String, String, AgentArgs), String> {
+        let model = overrides.model.as_ref().unwrap_or(&self.model);
+        let prompt = overrides.prompt.as_ref().unwrap_or(&self.prompt);
+        let args = match &overrides.args {
+            Some(args) => self.args.merge(args)?,
+            None => self.args.0.clone(),
+        };
+        Ok((model.clone(), prompt.clone(), args))
+    }
+}
+
+impl AppState {
+    /// Create an agent template in a workspace, as its first version, by its owner
+    pub async fn create_agent_template(
+        &self,
+        input: CreateAgentTemplate,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<AgentTemplate, AppError> {
+        self.verify_workspace_owner(ws_id, user_id, "manage agent templates")
+            .await?;
+        if self.agent_template_name_exists(ws_id, &input.name).await? {
+            info!(
+                "Agent template {} already exists in workspace {ws_id}",
+                input.name
+            );
+            return Err(AppError::CreateAgentError(format!(
+                "Agent template {} already exists",
+                input.name
+            )));
+        }
+        validate_args(&input.args, &self.config.agent_hosts).map_err(AppError::CreateAgentError)?;
+        validate_prompt(&input.prompt, &input.args).map_err(AppError::CreateAgentError)?;
+
+        let mut tx = self.pool.begin().await?;
+        let template: AgentTemplate = sqlx::query_as(
+            r#"
+            INSERT INTO agent_templates (ws_id, name, type, adapter, model, prompt, args)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(input.name)
+        .bind(input.r#type)
+        .bind(input.adapter)
+        .bind(input.model)
+        .bind(input.prompt)
+        .bind(Json(input.args))
+        .fetch_one(&mut *tx)
+        .await?;
+        insert_version(&mut tx, &template).await?;
+        tx.commit().await?;
+
+        Ok(template)
+    }
+
+    /// check if an agent template name exists in a workspace
+    pub async fn agent_template_name_exists(
+        &self,
+        ws_id: u64,
+        name: &str,
+    ) -> Result<bool, AppError> {
+        let exists = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM agent_templates WHERE ws_id = $1 AND name = $2)
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(name)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(exists)
+    }
+
+    /// List the agent templates of a workspace by name
+    pub async fn list_agent_templates(&self, ws_id: u64) -> Result<Vec<AgentTemplate>, AppError> {
+        let templates = sqlx::query_as(
+            r#"
+            SELECT * FROM agent_templates WHERE ws_id = $1 ORDER BY name ASC
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(templates)
+    }
+
+    pub async fn find_agent_template_by_id(
+        &self,
+        ws_id: u64,
+        id: u64,
+    ) -> Result<Option<AgentTemplate>, AppError> {
+        let template = sqlx::query_as(
+            r#"
+            SELECT * FROM agent_templates WHERE ws_id = $1 AND id = $2
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(template)
+    }
+
+    /// Save a new version of an agent template and apply it to the chat agents
+    /// attached to it, keeping their overrides. Only the workspace owner can, since
+    /// it changes agents of chats they may not be in.
+    pub async fn update_agent_template(
+        &self,
+        input: UpdateAgentTemplate,
+        ws_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<AgentTemplate, AppError> {
+        self.verify_workspace_owner(ws_id, user_id, "manage agent templates")
+            .await?;
+        // lock the template so concurrent updates apply on top of each other
+        let mut tx = self.pool.begin().await?;
+        let template: Option<AgentTemplate> = sqlx::query_as(
+            r#"
+            SELECT * FROM agent_templates WHERE ws_id = $1 AND id = $2 FOR UPDATE
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(mut template) = template else {
+            return Err(AppError::NotFound(format!("agent template id: {}", id)));
+        };
+        if let Some(model) = input.model.filter(|m| !m.is_empty()) {
+            template.model = model;
+        }
+        if let Some(prompt) = input.prompt.filter(|p| !p.is_empty()) {
+            template.prompt = prompt;
+        }
+        if let Some(args) = input.args {
+            validate_args(&args, &self.config.agent_hosts).map_err(AppError::UpdateAgentError)?;
+            template.args = Json(args);
+        }
+        validate_prompt(&template.prompt, &template.args).map_err(AppError::UpdateAgentError)?;
+
+        // attached agents must stay valid with their overrides
+        let agents = list_attached_agents(&mut tx, id).await?;
+        let mut updates = Vec::with_capacity(agents.len());
+        for agent in &agents {
+            let (model, prompt, args) = template
+                .apply(&agent.overrides)
+                .and_then(|(model, prompt, args)| {
+                    validate_prompt(&prompt, &args)?;
+                    Ok((model, prompt, args))
+                })
+                .map_err(|e| {
+                    AppError::UpdateAgentError(format!(
+                        "agent {} of chat {}: {}",
+                        agent.name, agent.chat_id, e
+                    ))
+                })?;
+            updates.push((agent.id, model, prompt, args));
+        }
+
+        let template: AgentTemplate = sqlx::query_as(
+            r#"
+            UPDATE agent_templates
+            SET model = $1, prompt = $2, args = $3, version = version + 1, updated_at = NOW()
+            WHERE id = $4
+            RETURNING *
+            "#,
+        )
+        .bind(&template.model)
+        .bind(&template.prompt)
+        .bind(&template.args)
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        insert_version(&mut tx, &template).await?;
+        for (agent_id, model, prompt, args) in updates {
+            sqlx::query(
+                r#"
+                UPDATE chat_agents
+                SET model = $1, prompt = $2, args = $3, updated_at = NOW()
+                WHERE id = $4
+                "#,
+            )
+            .bind(model)
+            .bind(prompt)
+            .bind(Json(args))
+            .bind(agent_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(template)
+    }
+
+    /// the versions of an agent template, latest first
+    pub async fn list_agent_template_versions(
+        &self,
+        ws_id: u64,
+        id: u64,
+    ) -> Result<Vec<AgentTemplateVersion>, AppError> {
+        if self.find_agent_template_by_id(ws_id, id).await?.is_none() {
+            return Err(AppError::NotFound(format!("agent template id: {}", id)));
+        }
+        let versions = sqlx::query_as(
+            r#"
+            SELECT * FROM agent_template_versions WHERE template_id = $1 ORDER BY version DESC
+            "#,
+        )
+        .bind(id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+
+    /// Add an agent following a template of the chat's workspace to a chat
+    pub async fn attach_agent_template(
+        &self,
+        input: AttachAgent,
+        chat_id: u64,
+    ) -> Result<ChatAgent, AppError> {
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id: {} not found", chat_id)))?;
+        let Some(template) = self
+            .find_agent_template_by_id(chat.ws_id as _, input.template_id)
+            .await?
+        else {
+            return Err(AppError::NotFound(format!(
+                "agent template id: {}",
+                input.template_id
+            )));
+        };
+        if self.agent_name_exists(chat_id, &template.name).await? {
+            return Err(AppError::CreateAgentError(format!(
+                "Agent {} already exists",
+                template.name
+            )));
+        }
+        if let Some(args) = &input.overrides.args {
+            validate_args(args, &self.config.agent_hosts).map_err(AppError::CreateAgentError)?;
+        }
+        let (model, prompt, args) = template
+            .apply(&input.overrides)
+            .map_err(AppError::CreateAgentError)?;
+        validate_args(&args, &self.config.agent_hosts).map_err(AppError::CreateAgentError)?;
+        validate_prompt(&prompt, &args).map_err(AppError::CreateAgentError)?;
+
+        let agent = sqlx::query_as(
+            r#"
+            INSERT INTO chat_agents (chat_id, name, type, adapter, model, prompt, args, position,
+                template_id, overrides)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, COALESCE($8, (
+                SELECT COALESCE(MAX(position), 0) + 1 FROM chat_agents WHERE chat_id = $1
+            )), $9, $10)
+            RETURNING *
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(&template.name)
+        .bind(&template.r#type)
+        .bind(&template.adapter)
+        .bind(model)
+        .bind(prompt)
+        .bind(Json(args))
+        .bind(input.position)
+        .bind(template.id)
+        .bind(Json(input.overrides))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(agent)
+    }
+}
+
+/// the chat agents following a template
+async fn list_attached_agents(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    template_id: u64,
+) -> Result<Vec<ChatAgent>, AppError> {
+    let agents = sqlx::query_as(
+        r#"
+        SELECT * FROM chat_agents WHERE template_id = $1 ORDER BY id ASC
+        "#,
+    )
+    .bind(template_id as i64)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(agents)
+}
+
+async fn insert_version(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    template: &AgentTemplate,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO agent_template_versions (template_id, version, model, prompt, args)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(template.id)
+    .bind(template.version)
+    .bind(&template.model)
+    .bind(&template.prompt)
+    .bind(&template.args)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+impl CreateAgentTemplate {
+    pub fn new(
+        name: impl Into<String>,
+        r#type: AgentType,
+        prompt: impl Into<String>,
+        args: impl Serialize,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            r#type,
+            adapter: AdapterType::Test,
+            model: "scripted".to_string(),
+            prompt: prompt.into(),
+            args: serde_json::from_value(serde_json::to_value(args).unwrap()).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UpdateAgent;
+    use anyhow::Result;
+
+    /// user 1 owns workspace 1 and 2
+    async fn state_with_owner() -> Result<(sqlx_db_tester::TestPg, AppState)> {
+        let (tdb, state) = AppState::new_for_test().await?;
+        sqlx::query("UPDATE workspaces SET owner_id = 1 WHERE id IN (1, 2)")
+            .execute(&state.pool)
+            .await?;
+        Ok((tdb, state))
+    }
+
+    #[tokio::test]
+    async fn agent_template_should_be_shared_by_chats() -> Result<()> {
+        let (_tdb, state) = state_with_owner().await?;
+        let input = CreateAgentTemplate::new(
+            "translator",
+            AgentType::Proxy,
+            "Translate to {{args.language}}",
+            serde_json::json!({ "language": "French", "temperature": 0.2 }),
+        );
+        let template = state.create_agent_template(input, 1, 1).await?;
+        assert_eq!(template.version, 1);
+
+        // names are unique in a workspace, not across workspaces
+        let input = CreateAgentTemplate::new(
+            "translator",
+            AgentType::Proxy,
+            "Translate",
+            serde_json::json!({}),
+        );
+        assert!(
+            state
+                .create_agent_template(input.clone(), 1, 1)
+                .await
+                .is_err()
+        );
+        state.create_agent_template(input, 2, 1).await?;
+
+        // the same template in two chats, one of them with overrides
+        let input = AttachAgent {
+            template_id: template.id as _,
+            ..Default::default()
+        };
+        let plain = state.attach_agent_template(input, 1).await?;
+        let input = AttachAgent {
+            template_id: template.id as _,
+            overrides: serde_json::from_value(
+                serde_json::json!({ "args": { "language": "German" } }),
+            )?,
+            position: None,
+        };
+        let german = state.attach_agent_template(input, 2).await?;
+        assert_eq!(plain.name, "translator");
+        assert_eq!(german.name, "translator");
+        assert_eq!(german.template_id, Some(template.id));
+        assert_eq!(german.args.extra["language"], "German");
+        assert_eq!(german.args.temperature, Some(0.2));
+
+        // updates reach every chat, keeping the overrides
+        let input = UpdateAgentTemplate {
+            prompt: Some("Translate into {{args.language}}".to_string()),
+            ..Default::default()
+        };
+        let template = state
+            .update_agent_template(input, 1, template.id as _, 1)
+            .await?;
+        assert_eq!(template.version, 2);
+        let plain = state.find_agent_by_id(1, plain.id as _).await?.unwrap();
+        let german = state.find_agent_by_id(2, german.id as _).await?.unwrap();
+        assert_eq!(plain.prompt, "Translate into {{args.language}}");
+        assert_eq!(german.prompt, "Translate into {{args.language}}");
+        assert_eq!(plain.args.extra["language"], "French");
+        assert_eq!(german.args.extra["language"], "German");
+
+        // a chat agent update becomes an override
+        let input = UpdateAgent {
+            id: german.id as _,
+            prompt: "Translate politely".to_string(),
+            ..Default::default()
+        };
+        let german = state.update_agent(input, 2).await?;
+        assert_eq!(
+            german.overrides.prompt.as_deref(),
+            Some("Translate politely")
+        );
+        let input = UpdateAgentTemplate {
+            model: Some("scripted-v2".to_string()),
+            ..Default::default()
+        };
+        state
+            .update_agent_template(input, 1, template.id as _, 1)
+            .await?;
+        let german = state.find_agent_by_id(2, german.id as _).await?.unwrap();
+        assert_eq!(german.prompt, "Translate politely");
+        assert_eq!(german.model, "scripted-v2");
+
+        let versions = state
+            .list_agent_template_versions(1, template.id as _)
+            .await?;
+        let history = versions
+            .iter()
+            .map(|v| (v.version, v.model.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            history,
+            vec![(3, "scripted-v2"), (2, "scripted"), (1, "scripted")]
+        );
+        assert_eq!(versions[2].prompt, "Translate to {{args.language}}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn agent_template_updates_should_keep_attached_agents_valid() -> Result<()> {
+        let (_tdb, state) = state_with_owner().await?;
+        let input = CreateAgentTemplate::new(
+            "translator",
+            AgentType::Proxy,
+            "Translate",
+            serde_json::json!({ "language": "French" }),
+        );
+        let template = state.create_agent_template(input, 1, 1).await?;
+        let input = AttachAgent {
+            template_id: template.id as _,
+            overrides: AgentOverrides {
+                prompt: Some("Translate to {{args.language}}".to_string()),
+                ..Default::default()
+            },
+            position: None,
+        };
+        state.attach_agent_template(input, 2).await?;
+
+        // the override uses an arg the update removes
+        let input = UpdateAgentTemplate {
+            args: Some(AgentArgs::default()),
+            ..Default::default()
+        };
+        let err = state
+            .update_agent_template(input, 1, template.id as _, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "update agent error: agent translator of chat 2: unknown variable in prompt: args.language"
+        );
+
+        // templates of another workspace can't be attached
+        let input = AttachAgent {
+            template_id: template.id as _,
+            ..Default::default()
+        };
+        let chat_id: i64 = sqlx::query_scalar(
+            "INSERT INTO chats (ws_id, type, members) VALUES (2, 'group', '{1, 2, 3}') RETURNING id",
+        )
+        .fetch_one(&state.pool)
+        .await?;
+        let err = state
+            .attach_agent_template(input, chat_id as _)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+    #[tokio::test]
+    async fn agent_templates_should_be_managed_by_workspace_owner() -> Result<()> {
+        let (_tdb, state) = state_with_owner().await?;
+        let input = CreateAgentTemplate::new(
+            "translator",
+            AgentType::Proxy,
+            "Translate",
+            serde_json::json!({}),
+        );
+        let err = state
+            .create_agent_template(input.clone(), 1, 2)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        let template = state.create_agent_template(input, 1, 1).await?;
+        let input = UpdateAgentTemplate {
+            prompt: Some("Translate politely".to_string()),
+            ..Default::default()
+        };
+        let err = state
+            .update_agent_template(input, 1, template.id as _, 2)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_agent_template_updates_should_not_be_lost() -> Result<()> {
+        let (_tdb, state) = state_with_owner().await?;
+        let input = CreateAgentTemplate::new(
+            "translator",
+            AgentType::Proxy,
+            "Translate",
+            serde_json::json!({}),
+        );
+        let template = state.create_agent_template(input, 1, 1).await?;
+
+        // another update holds the template while this one starts
+        let mut tx = state.pool.begin().await?;
+        sqlx::query("SELECT 1 FROM agent_templates WHERE id = $1 FOR UPDATE")
+            .bind(template.id)
+            .execute(&mut *tx)
+            .await?;
+        let update = tokio::spawn({
+            let state = state.clone();
+            let input = UpdateAgentTemplate {
+                model: Some("scripted-v2".to_string()),
+                ..Default::default()
+            };
+            async move {
+                state
+                    .update_agent_template(input, 1, template.id as _, 1)
+                    .await
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        sqlx::query(
+            "UPDATE agent_templates SET prompt = 'Translate politely', version = version + 1 WHERE id = $1",
+        )
+        .bind(template.id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        let updated = update.await??;
+        assert_eq!(updated.version, 3);
+        assert_eq!(updated.model, "scripted-v2");
+        assert_eq!(updated.prompt, "Translate politely");
+        Ok(())
+    }
+
+    #[test]
+    fn agent_template_args_should_merge_or_fail() {
+        let mut template = AgentTemplate {
+            id: 1,
+            ws_id: 1,
+            name: "translator".to_string(),
+            r#type: AgentType::Proxy,
+            adapter: AdapterType::Test,
+            model: "scripted".to_string(),
+            prompt: "Translate".to_string(),
+            args: Json(serde_json::from_value(serde_json::json!({ "temperature": 0.2 })).unwrap()),
+            version: 1,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let overrides = AgentOverrides {
+            args: Some(serde_json::from_value(serde_json::json!({ "top_p": 0.5 })).unwrap()),
+            ..Default::default()
+        };
+        let (_, _, args) = template.apply(&overrides).unwrap();
+        assert_eq!(args.temperature, Some(0.2));
+        assert_eq!(args.top_p, Some(0.5));
+
+        // an extra arg clashing with a known one doesn't silently fall back
+        template
+            .args
+            .extra
+            .insert("top_p".to_string(), serde_json::json!("high"));
+        let overrides = AgentOverrides {
+            args: Some(AgentArgs::default()),
+            ..Default::default()
+        };
+        let err = template.apply(&overrides).unwrap_err();
+        assert!(err.starts_with("invalid args: "), "{}", err);
+    }
+}
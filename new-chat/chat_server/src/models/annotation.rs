@@ -0,0 +1,86 @@
+use crate::{AppError, AppState};
+use chat_core::MessageAnnotation;
+
+impl AppState {
+    /// store the output of a tap agent for a message
+    pub async fn create_annotation(
+        &self,
+        message_id: u64,
+        agent_id: Option<i64>,
+        kind: &str,
+        value: serde_json::Value,
+    ) -> Result<MessageAnnotation, AppError> {
+        let annotation = sqlx::query_as(
+            r#"
+            INSERT INTO message_annotations (message_id, agent_id, kind, value)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(agent_id)
+        .bind(kind)
+        .bind(sqlx::types::Json(value))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(annotation)
+    }
+
+    /// list the annotations of a message in a chat, oldest first
+    pub async fn list_annotations(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Vec<MessageAnnotation>, AppError> {
+        let annotations = sqlx::query_as(
+            r#"
+            SELECT a.id, a.message_id, a.agent_id, a.kind, a.value, a.created_at
+            FROM message_annotations a
+            JOIN messages m ON m.id = a.message_id
+            WHERE m.chat_id = $1 AND a.message_id = $2
+            ORDER BY a.id ASC
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(annotations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn annotations_should_be_listed_per_message() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let agent = state.list_agents(1).await?.remove(0);
+
+        state
+            .create_annotation(1, Some(agent.id), "sentiment", json!("positive"))
+            .await?;
+        state
+            .create_annotation(1, None, "summary", json!({ "text": "hello" }))
+            .await?;
+        state
+            .create_annotation(2, None, "sentiment", json!("negative"))
+            .await?;
+
+        let annotations = state.list_annotations(1, 1).await?;
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].agent_id, Some(agent.id));
+        assert_eq!(annotations[0].kind, "sentiment");
+        assert_eq!(annotations[0].value.0, json!("positive"));
+        assert_eq!(annotations[1].value.0, json!({ "text": "hello" }));
+
+        // message 1 belongs to chat 1, not chat 2
+        assert!(state.list_annotations(2, 1).await?.is_empty());
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{AppError, AppState};
 use chat_core::{Chat, ChatType};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 use utoipa::ToSchema;
 
 #[derive(Debug, Default, ToSchema, Serialize, Deserialize)]
@@ -20,6 +22,23 @@ pub struct AddMembers {
     pub members: Vec<i64>,
 }
 
+#[derive(Debug, Default, ToSchema, Serialize, Deserialize)]
+pub struct MarkRead {
+    /// the last message read, defaults to the latest one of the chat
+    #[serde(default)]
+    pub message_id: Option<u64>,
+}
+
+/// How far a member has read a chat
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ReadReceipt {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub last_read_id: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
 impl AppState {
     pub async fn create_chat(
         &self,
@@ -76,15 +95,16 @@ impl AppState {
 
         let chat = sqlx::query_as(
             "
-            INSERT INTO chats (ws_id ,name, type, members)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, ws_id, name, type, members, agents, created_at
+            INSERT INTO chats (ws_id ,name, type, members, owner_id)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, ws_id, name, type, members, agents, owner_id, created_at
             ",
         )
         .bind(ws_id as i64)
         .bind(&input.name)
         .bind(chat_type)
         .bind(&input.members)
+        .bind(user_id as i64)
         .fetch_one(&self.pool)
         .await
         .map_err(|e| {
@@ -101,12 +121,21 @@ impl AppState {
         Ok(chat)
     }
 
+    /// the chats of the user with how many messages they haven't read yet
     pub async fn fetch_all_chats(&self, user_id: u64, ws_id: u64) -> Result<Vec<Chat>, AppError> {
         let chats = sqlx::query_as(
             "
-            SELECT id, ws_id, name, type, members, agents, created_at
-            FROM chats
-            WHERE ws_id = $1 AND $2 = ANY(members)
+            SELECT c.id, c.ws_id, c.name, c.type, c.members, c.agents, c.owner_id, c.created_at,
+                r.last_read_id,
+                (
+                    SELECT COUNT(*)
+                    FROM messages m
+                    WHERE m.chat_id = c.id AND m.id > COALESCE(r.last_read_id, 0)
+                    AND m.sender_id <> $2 AND NOT m.is_pending AND m.deleted_at IS NULL
+                ) AS unread_count
+            FROM chats c
+            LEFT JOIN chat_reads r ON r.chat_id = c.id AND r.user_id = $2
+            WHERE c.ws_id = $1 AND $2 = ANY(c.members)
             ",
         )
         .bind(ws_id as i64)
@@ -116,10 +145,68 @@ impl AppState {
         Ok(chats)
     }
 
+    /// Mark the messages of a chat read by `user_id` up to a message, the last read
+    /// message never moves back.
+    pub async fn mark_chat_read(
+        &self,
+        input: MarkRead,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ReadReceipt, AppError> {
+        let last_read_id: i64 = match input.message_id {
+            Some(id) => {
+                self.get_message_by_id(id)
+                    .await?
+                    .filter(|m| m.chat_id == chat_id as i64)
+                    .ok_or_else(|| AppError::NotFound(format!("message id: {} not found", id)))?
+                    .id
+            }
+            None => {
+                sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM messages WHERE chat_id = $1")
+                    .bind(chat_id as i64)
+                    .fetch_one(&self.pool)
+                    .await?
+            }
+        };
+
+        // only update when moving forward, so members aren't notified of stale reads
+        let receipt = sqlx::query_as(
+            r#"
+            INSERT INTO chat_reads (chat_id, user_id, last_read_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (chat_id, user_id) DO UPDATE
+            SET last_read_id = EXCLUDED.last_read_id, updated_at = NOW()
+            WHERE chat_reads.last_read_id < EXCLUDED.last_read_id
+            RETURNING chat_id, user_id, last_read_id, updated_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(last_read_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(receipt) = receipt {
+            return Ok(receipt);
+        }
+
+        let receipt = sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, last_read_id, updated_at
+            FROM chat_reads
+            WHERE chat_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(receipt)
+    }
+
     pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
         let chat = sqlx::query_as(
             "
-            SELECT id, ws_id, name, type, members, agents, created_at
+            SELECT id, ws_id, name, type, members, agents, owner_id, created_at
             FROM chats
             WHERE id = $1
             ",
@@ -158,7 +245,7 @@ impl AppState {
             UPDATE chats
             SET name = $1
             WHERE id = $2
-            RETURNING id, ws_id, name, type, members, agents, created_at
+            RETURNING id, ws_id, name, type, members, agents, owner_id, created_at
             ",
         )
         .bind(name)
@@ -187,7 +274,7 @@ impl AppState {
             UPDATE chats
             SET members = array(SELECT DISTINCT unnest(members || $1))
             WHERE id = $2
-            RETURNING id, ws_id, name, type, members, agents, created_at
+            RETURNING id, ws_id, name, type, members, agents, owner_id, created_at
             ",
         )
         .bind(member_ids)
@@ -265,6 +352,7 @@ impl CreateChat {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::CreateMessage;
     use anyhow::Result;
 
     #[tokio::test]
@@ -316,6 +404,51 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn mark_chat_read_should_update_unread_count() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let send = |content: &str| CreateMessage {
+            content: content.to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        let unread = |chats: Vec<Chat>, id: i64| {
+            let chat = chats.into_iter().find(|c| c.id == id).unwrap();
+            (chat.last_read_id, chat.unread_count)
+        };
+        // chat 3 is the single chat of user 1 and 2
+        let first = state.create_message(send("hi"), 3, 1).await?;
+        state.create_message(send("there?"), 3, 1).await?;
+        state.create_message(send("mine"), 3, 2).await?;
+        assert_eq!(unread(state.fetch_all_chats(2, 1).await?, 3), (None, 2));
+
+        let input = MarkRead {
+            message_id: Some(first.id as _),
+        };
+        let receipt = state.mark_chat_read(input, 3, 2).await?;
+        assert_eq!(receipt.last_read_id, first.id);
+        assert_eq!(
+            unread(state.fetch_all_chats(2, 1).await?, 3),
+            (Some(first.id), 1)
+        );
+
+        let receipt = state.mark_chat_read(MarkRead::default(), 3, 2).await?;
+        assert_eq!(unread(state.fetch_all_chats(2, 1).await?, 3).1, 0);
+        // reading an older message doesn't move back
+        let input = MarkRead {
+            message_id: Some(first.id as _),
+        };
+        let same = state.mark_chat_read(input, 3, 2).await?;
+        assert_eq!(same, receipt);
+
+        let input = MarkRead {
+            message_id: Some(1),
+        };
+        let err = state.mark_chat_read(input, 3, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn chat_is_member_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -1,15 +1,33 @@
-use crate::{AppError, AppState, agent::AgentVariant, models::ChatFile};
-use chat_core::{Agent, AgentContext, AgentDecision, ChatType, Message};
+use crate::{
+    AppError, AppState,
+    models::{ChatFile, mentioned_agents},
+};
+use ai_sdk::{CompletionChunk, CompletionStream, Usage};
+use anyhow::anyhow;
+use chat_core::{AgentContext, AgentError, AgentType, Message, MessageEdit};
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use sqlx::PgConnection;
+use std::{str::FromStr, time::Duration};
+use tokio::time;
 use tracing::warn;
 use utoipa::{IntoParams, ToSchema};
 
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
 pub struct CreateMessage {
+    /// `@name` mentions of the chat's agents make them reply
     pub content: String,
     #[serde(default)]
     pub files: Vec<String>,
+    /// reply in the thread of this message
+    #[serde(default)]
+    pub parent_id: Option<u64>,
+}
+
+/// the new content of an edited message
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct UpdateMessage {
+    pub content: String,
 }
 
 #[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
@@ -20,13 +38,47 @@ pub struct ListMessages {
     pub limit: u64,
 }
 
+/// A top level message with the replies in its thread
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct Thread {
+    pub message: Message,
+    /// newest first, like the messages of a chat
+    pub replies: Vec<Message>,
+}
+
+/// number of recent messages handed to agents as conversation history
+const AGENT_HISTORY_SIZE: u64 = 20;
+
+// pg_notify('chat_message_delta', ...) payload, one per streamed chunk
+#[derive(Debug, Serialize)]
+struct ChatMessageDelta<'a> {
+    chat_id: i64,
+    message_id: i64,
+    seq: u64,
+    chunk: &'a str,
+    members: &'a [i64],
+}
+
 impl AppState {
+    /// Send a message, see [`AppState::send_message`] to know if its agents were skipped
     pub async fn create_message(
         &self,
         input: CreateMessage,
         chat_id: u64,
         user_id: u64,
     ) -> Result<Message, AppError> {
+        let (message, _) = self.send_message(input, chat_id, user_id).await?;
+        Ok(message)
+    }
+
+    /// Send a message, returns it along with why its agents didn't run, if they didn't.
+    /// A message is sent as long as moderators let it through.
+    pub async fn send_message(
+        &self,
+        input: CreateMessage,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<(Message, Option<String>), AppError> {
         let base_dir = &self.config.server.base_dir;
         // verify content - not empty
         if input.content.is_empty() {
@@ -46,81 +98,395 @@ impl AppState {
             }
         }
 
-        // if we have agent, apply it and get the result
-        let mut agents = self.list_agents(chat_id).await?;
-        let decision = if let Some(agent) = agents.pop() {
-            let agent: AgentVariant = agent.into();
-            match agent {
-                AgentVariant::Proxy(agent) => {
-                    agent
-                        .process(&input.content, &AgentContext::default())
-                        .await?
-                }
-                _ => AgentDecision::None,
+        // threads are one level deep, replying to a reply is replying in its thread
+        let parent_id = match input.parent_id {
+            Some(id) => {
+                let parent = self
+                    .get_message_by_id(id)
+                    .await?
+                    .filter(|m| m.chat_id == chat_id as i64 && m.deleted_at.is_none())
+                    .ok_or_else(|| {
+                        AppError::CreateMessageError(format!("Message {} doesn't exist", id))
+                    })?;
+                Some(parent.parent_id.unwrap_or(parent.id))
             }
-        } else {
-            AgentDecision::None
+            None => None,
         };
 
-        let modified_content = match decision {
-            AgentDecision::Modify(ref s) => Some(s),
-            _ => None,
+        // moderation agents check the message before it's sent, the others run later in
+        // the agent worker: a run is enqueued along with the message so it's never lost,
+        // modified content and replies arrive as update events
+        // a workspace out of AI quota still gets its message through, without agents and
+        // flagged for review by its moderators
+        let (moderators, agents): (Vec<_>, Vec<_>) = self
+            .list_agents(chat_id)
+            .await?
+            .into_iter()
+            .partition(|agent| agent.r#type == AgentType::Moderate);
+        let mentions = mentioned_agents(&input.content, &agents);
+        let mut has_agents = !agents.is_empty();
+        let mut agents_skipped = None;
+        let mut moderation = None;
+        if has_agents || !moderators.is_empty() {
+            let chat = self
+                .get_chat_by_id(chat_id)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("chat id: {} not found", chat_id)))?;
+            if let Err(e) = self.check_ai_quota(chat.ws_id as _).await {
+                has_agents = false;
+                agents_skipped = Some(e.to_string());
+            }
+            if !moderators.is_empty() {
+                let checked = self
+                    .check_message(&moderators, chat_id, user_id, &input.content)
+                    .await?;
+                moderation = Some(checked);
+            }
+        }
+        let content = match &moderation {
+            Some((checked, _)) => checked.content.clone(),
+            None => input.content.clone(),
         };
-
-        // create message
+        let mut tx = self.pool.begin().await?;
         let message: Message = sqlx::query_as(
             r#"
-          INSERT INTO messages (chat_id, sender_id, content, modified_content, files)
+          INSERT INTO messages (chat_id, sender_id, content, files, parent_id)
           VALUES ($1, $2, $3, $4, $5)
           RETURNING *
           "#,
         )
         .bind(chat_id as i64)
         .bind(user_id as i64)
-        .bind(input.content)
-        .bind(modified_content)
+        .bind(content)
         .bind(&input.files)
+        .bind(parent_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some((checked, ctx)) = &moderation {
+            checked
+                .save(&mut tx, ctx, &input.content, Some(message.id))
+                .await?;
+        }
+        if has_agents {
+            sqlx::query(
+                "INSERT INTO agent_runs (message_id, chat_id, mentions) VALUES ($1, $2, $3)",
+            )
+            .bind(message.id)
+            .bind(message.chat_id)
+            .bind(&mentions)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok((message, agents_skipped))
+    }
+
+    pub async fn get_message_by_id(&self, id: u64) -> Result<Option<Message>, AppError> {
+        let message = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, modified_content, files, is_pending, agent_id,
+                reply_to_id, parent_id, reply_count, last_reply_at, edited_at, deleted_at, created_at
+            FROM messages
+            WHERE id = $1
+            "#,
+        )
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(message)
+    }
+
+    /// set the content rewritten by proxy agents, members get it as an update event.
+    /// None if the message was deleted in the meantime, the content is dropped.
+    pub async fn set_modified_content(
+        &self,
+        message_id: u64,
+        content: &str,
+    ) -> Result<Option<Message>, AppError> {
+        let message = sqlx::query_as(
+            r#"
+            UPDATE messages
+            SET modified_content = $1
+            WHERE id = $2 AND deleted_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(content)
+        .bind(message_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(message)
+    }
+
+    /// load the chat, workspace, sender and the history before a message, for agents to
+    /// process it. The history of a reply is its thread, starting with the parent.
+    pub async fn load_agent_context(&self, message: &Message) -> Result<AgentContext, AppError> {
+        self.load_context(
+            message.chat_id as _,
+            message.sender_id,
+            message.parent_id,
+            Some(message.id as _),
+        )
+        .await
+    }
+
+    /// the context of a top level message `sender_id` would send to a chat after
+    /// `last_id`, or after the latest message if not set
+    pub async fn load_chat_context(
+        &self,
+        chat_id: u64,
+        sender_id: i64,
+        last_id: Option<u64>,
+    ) -> Result<AgentContext, AppError> {
+        self.load_context(chat_id, sender_id, None, last_id).await
+    }
+
+    async fn load_context(
+        &self,
+        chat_id: u64,
+        sender_id: i64,
+        parent_id: Option<i64>,
+        last_id: Option<u64>,
+    ) -> Result<AgentContext, AppError> {
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id: {} not found", chat_id)))?;
+        let ws_name = self
+            .find_workspace_by_id(chat.ws_id as _)
+            .await?
+            .map(|ws| ws.name)
+            .unwrap_or_default();
+        let sender = self.find_user_by_id(sender_id).await?;
+        let members = self.fetch_chat_user_by_ids(&chat.members).await?;
+        let input = ListMessages {
+            last_id,
+            limit: AGENT_HISTORY_SIZE,
+        };
+        let mut history = self.fetch_messages(chat_id, parent_id, input).await?;
+        history.retain(|m| !m.is_pending);
+        history.reverse();
+        if let Some(parent_id) = parent_id
+            && let Some(parent) = self.get_message_by_id(parent_id as _).await?
+        {
+            history.insert(0, parent);
+        }
+
+        Ok(AgentContext {
+            chat_id: chat.id,
+            chat_name: chat.name,
+            chat_type: chat.r#type,
+            ws_id: chat.ws_id,
+            ws_name,
+            sender,
+            members,
+            history,
+        })
+    }
+
+    /// create an empty pending message, to be filled in by `stream_reply`, on behalf
+    /// of the agent writing it if any, quoting `reply_to_id`
+    pub async fn create_pending_message(
+        &self,
+        chat_id: u64,
+        sender_id: i64,
+        agent_id: Option<i64>,
+        reply_to_id: Option<i64>,
+        parent_id: Option<i64>,
+    ) -> Result<Message, AppError> {
+        let message = sqlx::query_as(
+            r#"
+            INSERT INTO messages (chat_id, sender_id, content, is_pending, agent_id, reply_to_id,
+                parent_id)
+            VALUES ($1, $2, '', TRUE, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(sender_id)
+        .bind(agent_id)
+        .bind(reply_to_id)
+        .bind(parent_id)
         .fetch_one(&self.pool)
         .await?;
 
-        // if decision is reply, create a new message
-        if let AgentDecision::Reply(reply) = decision {
-            let chat = self
-                .get_chat_by_id(chat_id)
-                .await?
-                .expect("chat should exist");
-            if chat.r#type != ChatType::Single {
+        Ok(message)
+    }
+
+    /// Push every delta of the stream to the chat members, then finalize the pending
+    /// message. Returns it with the usage the stream ended with, None if it was deleted
+    /// meanwhile.
+    ///
+    /// If the stream fails or stalls, the content streamed so far is kept and the error
+    /// returned. If nothing arrived the pending message is removed and the error is the
+    /// agent's, so the run can be tried again.
+    pub async fn stream_reply(
+        &self,
+        message: Message,
+        members: &[i64],
+        mut stream: CompletionStream,
+    ) -> Result<(Option<Message>, Usage), AppError> {
+        let mut content = String::new();
+        let mut usage = Usage::default();
+        let mut seq = 0;
+        let mut failure = None;
+        // a stalled stream would hold up the worker, every chunk gets the agent timeout
+        let idle = Duration::from_secs(self.config.agent_worker.timeout_secs);
+        loop {
+            let chunk = match time::timeout(idle, stream.next()).await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(_) => {
+                    failure = Some(AgentError::Timeout(idle));
+                    break;
+                }
+            };
+            let chunk = match chunk {
+                Ok(CompletionChunk::Delta(chunk)) => chunk,
+                Ok(CompletionChunk::Usage(u)) => {
+                    usage = u;
+                    continue;
+                }
+                Err(e) => {
+                    failure = Some(AgentError::from(e));
+                    break;
+                }
+            };
+            let payload = ChatMessageDelta {
+                chat_id: message.chat_id,
+                message_id: message.id,
+                seq,
+                chunk: &chunk,
+                members,
+            };
+            sqlx::query("SELECT pg_notify('chat_message_delta', $1::text)")
+                .bind(sqlx::types::Json(payload))
+                .execute(&self.pool)
+                .await?;
+            content.push_str(&chunk);
+            seq += 1;
+        }
+
+        match failure {
+            None => {
+                let message = self.finalize_message(message.id as _, &content).await?;
+                Ok((message, usage))
+            }
+            Some(e) if content.is_empty() => {
+                warn!("reply stream for message {} failed: {}", message.id, e);
+                self.discard_pending_message(message.id as _).await?;
+                Err(e.into())
+            }
+            Some(e) => {
                 warn!(
-                    "reply decision found in non single chat {}. reply: {}",
-                    chat_id, reply
+                    "reply stream for message {} failed after {} chunks: {}",
+                    message.id, seq, e
                 );
+                self.finalize_message(message.id as _, &content).await?;
+                Err(AppError::AnyError(anyhow!(
+                    "reply stream failed after {} chunks: {}",
+                    seq,
+                    e
+                )))
             }
-            let other_user_id = chat
-                .members
-                .into_iter()
-                .find(|m| m != &(user_id as i64))
-                .expect("other user should exist");
-            let _: (i64,) = sqlx::query_as(
-                r#"
-              INSERT INTO messages (chat_id, sender_id, content)
-              VALUES ($1, $2, $3)
-              RETURNING id
-              "#,
-            )
-            .bind(chat_id as i64)
-            .bind(other_user_id)
-            .bind(reply)
-            .fetch_one(&self.pool)
-            .await?;
         }
+    }
+
+    /// Remove the pending messages created more than `max_age` ago as tombstones, left
+    /// behind by a writer that died or failed to finalize them. Returns how many.
+    pub async fn sweep_pending_messages(&self, max_age: Duration) -> Result<usize, AppError> {
+        let ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM messages
+            WHERE is_pending AND deleted_at IS NULL
+            AND created_at < NOW() - $1 * INTERVAL '1 second'
+            "#,
+        )
+        .bind(max_age.as_secs_f64())
+        .fetch_all(&self.pool)
+        .await?;
+        for id in &ids {
+            warn!("pending message {} was never finalized, removing it", id);
+            self.discard_pending_message(*id as _).await?;
+        }
+        Ok(ids.len())
+    }
+
+    /// remove a pending message that won't get any content, as a tombstone
+    pub async fn discard_pending_message(&self, message_id: u64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        tombstone_message(&mut tx, message_id as _).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// set the final content of a pending message, None if it was deleted in the
+    /// meantime, the content is dropped
+    pub async fn finalize_message(
+        &self,
+        message_id: u64,
+        content: &str,
+    ) -> Result<Option<Message>, AppError> {
+        let message = sqlx::query_as(
+            r#"
+            UPDATE messages
+            SET content = $1, is_pending = FALSE
+            WHERE id = $2 AND deleted_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(content)
+        .bind(message_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
 
         Ok(message)
     }
 
+    /// top level messages of a chat with their reactions, newest first, deleted ones
+    /// as tombstones
     pub async fn list_messages(
         &self,
         input: ListMessages,
         chat_id: u64,
+    ) -> Result<Vec<Message>, AppError> {
+        let mut messages = self.fetch_messages(chat_id, None, input).await?;
+        self.load_reactions(&mut messages).await?;
+        Ok(messages)
+    }
+
+    /// a top level message of a chat with the replies in its thread
+    pub async fn list_thread(
+        &self,
+        input: ListMessages,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Thread, AppError> {
+        let message = self
+            .get_message_by_id(message_id)
+            .await?
+            .filter(|m| m.chat_id == chat_id as i64 && m.parent_id.is_none())
+            .ok_or_else(|| AppError::NotFound(format!("thread of message {}", message_id)))?;
+        let mut replies = self
+            .fetch_messages(chat_id, Some(message.id), input)
+            .await?;
+        replies.push(message);
+        self.load_reactions(&mut replies).await?;
+        let message = replies.pop().expect("message should be there");
+
+        Ok(Thread { message, replies })
+    }
+
+    /// the messages of a thread, or the top level ones if `parent_id` isn't set
+    async fn fetch_messages(
+        &self,
+        chat_id: u64,
+        parent_id: Option<i64>,
+        input: ListMessages,
     ) -> Result<Vec<Message>, AppError> {
         let last_id = input.last_id.unwrap_or(i64::MAX as _);
         let limit = match input.limit {
@@ -129,77 +495,243 @@ impl AppState {
             _ => 100,
         };
 
-        let messages: Vec<Message> = sqlx::query_as(
-            r#"
-        SELECT id, chat_id, sender_id, content, modified_content, files, created_at
+        // top level messages are matched with IS NULL, so they use their partial index
+        let query = match parent_id {
+            Some(parent_id) => sqlx::query_as(
+                r#"
+        SELECT id, chat_id, sender_id, content, modified_content, files, is_pending, agent_id,
+            reply_to_id, parent_id, reply_count, last_reply_at, edited_at, deleted_at, created_at
         FROM messages
         WHERE chat_id = $1
+        AND parent_id = $2
+        AND id < $3
+        ORDER BY id DESC
+        LIMIT $4
+        "#,
+            )
+            .bind(chat_id as i64)
+            .bind(parent_id),
+            None => sqlx::query_as(
+                r#"
+        SELECT id, chat_id, sender_id, content, modified_content, files, is_pending, agent_id,
+            reply_to_id, parent_id, reply_count, last_reply_at, edited_at, deleted_at, created_at
+        FROM messages
+        WHERE chat_id = $1
+        AND parent_id IS NULL
         AND id < $2
         ORDER BY id DESC
         LIMIT $3
         "#,
-        )
-        .bind(chat_id as i64)
-        .bind(last_id as i64)
-        .bind(limit)
-        .fetch_all(&self.pool)
-        .await?;
+            )
+            .bind(chat_id as i64),
+        };
+        let messages: Vec<Message> = query
+            .bind(last_id as i64)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
 
         Ok(messages)
     }
 
-    pub async fn delete_message(
+    /// Edit a message of `user_id`, the previous content is kept in `message_edits`.
+    /// Edits go through the chat's moderation agents like new messages, content
+    /// rewritten by proxy agents is dropped since it's about the old content.
+    pub async fn update_message(
         &self,
+        input: UpdateMessage,
         chat_id: u64,
         message_id: u64,
         user_id: u64,
-    ) -> Result<(), AppError> {
-        // verify message exists and belongs to the chat
+    ) -> Result<Message, AppError> {
+        if input.content.is_empty() {
+            return Err(AppError::UpdateMessageError(
+                "Content cannot be empty".to_string(),
+            ));
+        }
+        let message = self
+            .get_message_by_id(message_id)
+            .await?
+            .filter(|m| m.chat_id == chat_id as i64 && m.deleted_at.is_none())
+            .ok_or_else(|| AppError::NotFound(format!("message id: {} not found", message_id)))?;
+        if message.sender_id != user_id as i64 {
+            return Err(AppError::UpdateMessageError(
+                "You are not authorized to edit this message".to_string(),
+            ));
+        }
+        if message.is_pending {
+            return Err(AppError::UpdateMessageError(
+                "A pending message cannot be edited".to_string(),
+            ));
+        }
+        if message.content == input.content {
+            return Ok(message);
+        }
+
+        let moderators = self
+            .list_agents(chat_id)
+            .await?
+            .into_iter()
+            .filter(|agent| agent.r#type == AgentType::Moderate)
+            .collect::<Vec<_>>();
+        let moderation = if moderators.is_empty() {
+            None
+        } else {
+            let checked = self
+                .check_message(&moderators, chat_id, user_id, &input.content)
+                .await?;
+            Some(checked)
+        };
+        let content = match &moderation {
+            Some((checked, _)) => checked.content.clone(),
+            None => input.content.clone(),
+        };
+
+        let mut tx = self.pool.begin().await?;
+        // lock the message so concurrent edits keep every version
+        let previous: String =
+            sqlx::query_scalar("SELECT content FROM messages WHERE id = $1 FOR UPDATE")
+                .bind(message.id)
+                .fetch_one(&mut *tx)
+                .await?;
+        sqlx::query("INSERT INTO message_edits (message_id, content) VALUES ($1, $2)")
+            .bind(message.id)
+            .bind(previous)
+            .execute(&mut *tx)
+            .await?;
         let message: Message = sqlx::query_as(
             r#"
-            SELECT id, chat_id, sender_id, content, modified_content, files, created_at
-            FROM messages
-            WHERE id = $1 AND chat_id = $2
+            UPDATE messages
+            SET content = $1, modified_content = NULL, edited_at = NOW()
+            WHERE id = $2
+            RETURNING *
+            "#,
+        )
+        .bind(content)
+        .bind(message.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if let Some((checked, ctx)) = &moderation {
+            checked
+                .save(&mut tx, ctx, &input.content, Some(message.id))
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(message)
+    }
+
+    /// the previous versions of a message, oldest first
+    pub async fn list_message_edits(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Vec<MessageEdit>, AppError> {
+        let edits = sqlx::query_as(
+            r#"
+            SELECT e.id, e.message_id, e.content, e.created_at
+            FROM message_edits e
+            JOIN messages m ON m.id = e.message_id
+            WHERE m.chat_id = $1 AND e.message_id = $2
+            ORDER BY e.id ASC
             "#,
         )
-        .bind(message_id as i64)
         .bind(chat_id as i64)
-        .fetch_one(&self.pool)
+        .bind(message_id as i64)
+        .fetch_all(&self.pool)
         .await?;
 
-        // verify user is the sender
-        if message.sender_id != user_id as i64 {
+        Ok(edits)
+    }
+
+    /// Delete a message, allowed for its sender and the owners of the chat and of the
+    /// workspace. The message is kept as a tombstone without content, files or edits
+    /// and members get it as a delete event.
+    pub async fn delete_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        // verify message exists and belongs to the chat
+        let message = self
+            .get_message_by_id(message_id)
+            .await?
+            .filter(|m| m.chat_id == chat_id as i64 && m.deleted_at.is_none())
+            .ok_or_else(|| AppError::NotFound(format!("message id: {} not found", message_id)))?;
+
+        // verify user is the sender or an owner
+        if message.sender_id != user_id as i64 && !self.owns_chat(chat_id, user_id).await? {
             return Err(AppError::DeleteMessageError(
                 "You are not authorized to delete this message".to_string(),
             ));
         }
 
-        // delete the message
-        let _: (i64,) = sqlx::query_as(
+        let mut conn = self.pool.acquire().await?;
+        tombstone_message(&mut conn, message.id).await
+    }
+
+    /// whether the user owns the chat or its workspace
+    async fn owns_chat(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let owns = sqlx::query_scalar(
             r#"
-            DELETE FROM messages WHERE id = $1
+            SELECT EXISTS (
+                SELECT 1 FROM chats c JOIN workspaces w ON w.id = c.ws_id
+                WHERE c.id = $1 AND (c.owner_id = $2 OR w.owner_id = $2)
+            )
             "#,
         )
-        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
         .fetch_one(&self.pool)
         .await?;
 
-        Ok(())
+        Ok(owns)
     }
 }
 
+/// soft delete a message, dropping its content and previous versions
+pub(super) async fn tombstone_message(
+    conn: &mut PgConnection,
+    message_id: i64,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE messages
+        SET content = '', modified_content = NULL, files = '{}', is_pending = FALSE,
+            deleted_at = NOW()
+        WHERE id = $1 AND deleted_at IS NULL
+        "#,
+    )
+    .bind(message_id)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query("DELETE FROM message_edits WHERE message_id = $1")
+        .bind(message_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM message_reactions WHERE message_id = $1")
+        .bind(message_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{AgentRunStatus, CreateAgent, CreateChat};
     use anyhow::Result;
+    use chat_core::{AdapterType, AgentType};
 
-    #[ignore]
     #[tokio::test]
     async fn create_message_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec![],
+            parent_id: None,
         };
         let message = state
             .create_message(input, 1, 1)
@@ -211,6 +743,7 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec!["1".to_string()],
+            parent_id: None,
         };
 
         let err = state.create_message(input, 1, 1).await.unwrap_err();
@@ -221,6 +754,7 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec![url],
+            parent_id: None,
         };
 
         let message = state
@@ -233,6 +767,38 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn create_message_should_enqueue_agent_run() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        // chat 3 has no agents
+        let message = state.create_message(input.clone(), 3, 1).await?;
+        assert!(state.list_agent_runs(message.id as _).await?.is_empty());
+
+        let input_agent = CreateAgent::new(
+            "broken",
+            AgentType::Proxy,
+            AdapterType::Ollama,
+            "llama3.2",
+            "You are a helpful assistant",
+            serde_json::json!({}),
+        );
+        state.create_agent(input_agent, 3).await?;
+        // the agents don't run inline, the message is returned as is
+        let message = state.create_message(input, 3, 1).await?;
+        assert_eq!(message.content, "hello");
+        assert_eq!(message.modified_content, None);
+        let runs = state.list_agent_runs(message.id as _).await?;
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].status, AgentRunStatus::Pending);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn list_messages_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -257,6 +823,342 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn stream_reply_should_finalize_pending_message() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let pending = state.create_pending_message(3, 2, None, None, None).await?;
+        assert!(pending.is_pending);
+        assert_eq!(pending.content, "");
+
+        let usage = Usage {
+            prompt_tokens: 3,
+            completion_tokens: 2,
+        };
+        let chunks = vec![
+            Ok(CompletionChunk::Delta("Hel".to_string())),
+            Ok(CompletionChunk::Delta("lo".to_string())),
+            Ok(CompletionChunk::Usage(usage)),
+        ];
+        let stream = futures_util::stream::iter(chunks).boxed();
+        let (message, reply_usage) = state.stream_reply(pending, &[1, 2], stream).await?;
+        let message = message.expect("reply should be finalized");
+        assert!(!message.is_pending);
+        assert_eq!(message.content, "Hello");
+        assert_eq!(reply_usage, usage);
+
+        let messages = state
+            .list_messages(
+                ListMessages {
+                    last_id: None,
+                    limit: 1,
+                },
+                3,
+            )
+            .await?;
+        assert_eq!(messages[0].id, message.id);
+        assert_eq!(messages[0].content, "Hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deleted_pending_reply_should_stay_tombstone() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let pending = state.create_pending_message(3, 2, None, None, None).await?;
+        state.delete_message(3, pending.id as _, 2).await?;
+
+        let chunks = vec![Ok(CompletionChunk::Delta("too late".to_string()))];
+        let stream = futures_util::stream::iter(chunks).boxed();
+        let (message, _) = state.stream_reply(pending.clone(), &[1, 2], stream).await?;
+        assert!(message.is_none());
+        let updated = state
+            .set_modified_content(pending.id as _, "too late")
+            .await?;
+        assert!(updated.is_none());
+
+        let message = state
+            .get_message_by_id(pending.id as _)
+            .await?
+            .expect("tombstone should exist");
+        assert!(message.deleted_at.is_some());
+        assert!(!message.is_pending);
+        assert_eq!(message.content, "");
+        assert_eq!(message.modified_content, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stale_pending_messages_should_be_swept() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let stale = state.create_pending_message(3, 2, None, None, None).await?;
+        let fresh = state.create_pending_message(3, 2, None, None, None).await?;
+        sqlx::query("UPDATE messages SET created_at = NOW() - INTERVAL '1 hour' WHERE id = $1")
+            .bind(stale.id)
+            .execute(&state.pool)
+            .await?;
+
+        let swept = state
+            .sweep_pending_messages(Duration::from_secs(600))
+            .await?;
+        assert_eq!(swept, 1);
+        let stale = state
+            .get_message_by_id(stale.id as _)
+            .await?
+            .expect("tombstone should exist");
+        assert!(stale.deleted_at.is_some());
+        assert!(!stale.is_pending);
+        let fresh = state
+            .get_message_by_id(fresh.id as _)
+            .await?
+            .expect("message should exist");
+        assert!(fresh.is_pending);
+        assert!(fresh.deleted_at.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn load_agent_context_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ListMessages {
+            last_id: None,
+            limit: 1,
+        };
+        let message = state.list_messages(input, 1).await?.remove(0);
+        let ctx = state.load_agent_context(&message).await?;
+
+        assert_eq!(ctx.chat_id, 1);
+        assert_eq!(ctx.chat_name.as_deref(), Some("general"));
+        assert_eq!(ctx.ws_name, "acme");
+        assert_eq!(
+            ctx.sender.expect("sender should exist").id,
+            message.sender_id
+        );
+        assert_eq!(ctx.members.len(), 5);
+        // only the messages before it
+        assert_eq!(ctx.history.len(), 9);
+        assert!(ctx.history.iter().all(|m| m.id < message.id));
+        // oldest first
+        assert!(ctx.history.windows(2).all(|w| w[0].id < w[1].id));
+        assert_eq!(ctx.history[0].content, "Hello, world!");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_message_should_keep_history() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "helo".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        // chat 3 has no agents
+        let message = state.create_message(input, 3, 1).await?;
+        assert_eq!(message.edited_at, None);
+        state.set_modified_content(message.id as _, "HELO").await?;
+
+        for content in ["hello", "hello!"] {
+            let input = UpdateMessage {
+                content: content.to_string(),
+            };
+            state.update_message(input, 3, message.id as _, 1).await?;
+        }
+        let message = state
+            .get_message_by_id(message.id as _)
+            .await?
+            .expect("message should exist");
+        assert_eq!(message.content, "hello!");
+        assert_eq!(message.modified_content, None);
+        assert!(message.edited_at.is_some());
+
+        let edits = state.list_message_edits(3, message.id as _).await?;
+        let contents = edits.iter().map(|e| e.content.as_str()).collect::<Vec<_>>();
+        assert_eq!(contents, vec!["helo", "hello"]);
+        assert!(
+            state
+                .list_message_edits(1, message.id as _)
+                .await?
+                .is_empty()
+        );
+
+        let input = UpdateMessage {
+            content: "hijacked".to_string(),
+        };
+        let err = state
+            .update_message(input, 3, message.id as _, 2)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::UpdateMessageError(_)));
+        let input = UpdateMessage {
+            content: "hello".to_string(),
+        };
+        let err = state
+            .update_message(input, 1, message.id as _, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_should_leave_tombstone() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let send = |content: &str| CreateMessage {
+            content: content.to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        // chat 4 is a group of user 1, 3 and 4, created by no one
+        let first = state.create_message(send("oops"), 4, 3).await?;
+        let input = UpdateMessage {
+            content: "oops!".to_string(),
+        };
+        state.update_message(input, 4, first.id as _, 3).await?;
+
+        let err = state.delete_message(4, first.id as _, 4).await.unwrap_err();
+        assert!(matches!(err, AppError::DeleteMessageError(_)));
+        state.delete_message(4, first.id as _, 3).await?;
+
+        let input = ListMessages {
+            last_id: None,
+            limit: 1,
+        };
+        let message = state.list_messages(input, 4).await?.remove(0);
+        assert_eq!(message.id, first.id);
+        assert!(message.deleted_at.is_some());
+        assert_eq!(message.content, "");
+        assert!(state.list_message_edits(4, first.id as _).await?.is_empty());
+        let err = state.delete_message(4, first.id as _, 3).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        let input = UpdateMessage {
+            content: "back".to_string(),
+        };
+        let err = state
+            .update_message(input, 4, first.id as _, 3)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // the workspace owner moderates every chat
+        let second = state.create_message(send("spam"), 4, 3).await?;
+        state.update_workspace_owner(1, 1).await?;
+        state.delete_message(4, second.id as _, 1).await?;
+
+        // the chat owner moderates their chat
+        let input = CreateChat::new("", &[2, 4, 5], false);
+        let chat = state.create_chat(&input, 4, 1).await?;
+        assert_eq!(chat.owner_id, Some(4));
+        let third = state.create_message(send("spam"), chat.id as _, 5).await?;
+        state.delete_message(chat.id as _, third.id as _, 4).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn thread_replies_should_be_counted_on_parent() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let send = |content: &str, parent_id: Option<i64>| CreateMessage {
+            content: content.to_string(),
+            files: vec![],
+            parent_id: parent_id.map(|id| id as _),
+        };
+        let parent = state.create_message(send("lunch?", None), 4, 1).await?;
+        let first = state
+            .create_message(send("sure", Some(parent.id)), 4, 3)
+            .await?;
+        assert_eq!(first.parent_id, Some(parent.id));
+        // replying to a reply stays in the thread of the parent
+        let second = state
+            .create_message(send("me too", Some(first.id)), 4, 4)
+            .await?;
+        assert_eq!(second.parent_id, Some(parent.id));
+
+        let err = state
+            .create_message(send("nope", Some(1)), 4, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::CreateMessageError(_)));
+
+        let input = ListMessages {
+            last_id: None,
+            limit: 0,
+        };
+        let messages = state.list_messages(input.clone(), 4).await?;
+        assert_eq!(messages[0].id, parent.id);
+        assert!(messages.iter().all(|m| m.parent_id.is_none()));
+        let thread = state.list_thread(input.clone(), 4, parent.id as _).await?;
+        assert_eq!(thread.message.reply_count, 2);
+        assert_eq!(thread.message.last_reply_at, Some(second.created_at));
+        let ids: Vec<_> = thread.replies.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [second.id, first.id]);
+
+        let ctx = state.load_agent_context(&second).await?;
+        let history: Vec<_> = ctx.history.iter().map(|m| m.id).collect();
+        assert_eq!(history, [parent.id, first.id]);
+
+        state.delete_message(4, second.id as _, 4).await?;
+        let thread = state.list_thread(input.clone(), 4, parent.id as _).await?;
+        assert_eq!(thread.message.reply_count, 1);
+        assert_eq!(thread.message.last_reply_at, Some(first.created_at));
+        state.delete_message(4, first.id as _, 3).await?;
+        let thread = state.list_thread(input.clone(), 4, parent.id as _).await?;
+        assert_eq!(thread.message.reply_count, 0);
+        assert_eq!(thread.message.last_reply_at, None);
+        let err = state
+            .list_thread(input, 4, first.id as _)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn thread_replies_should_not_notify_parent_update() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let send = |content: &str, parent_id: Option<i64>| CreateMessage {
+            content: content.to_string(),
+            files: vec![],
+            parent_id: parent_id.map(|id| id as _),
+        };
+        let parent = state.create_message(send("lunch?", None), 4, 1).await?;
+        let mut listener = sqlx::postgres::PgListener::connect_with(&state.pool).await?;
+        listener.listen("chat_message_updated").await?;
+
+        // the reply bumps the reply count of the parent, that's not an edit
+        state
+            .create_message(send("sure", Some(parent.id)), 4, 3)
+            .await?;
+        let input = UpdateMessage {
+            content: "lunch at noon?".to_string(),
+        };
+        state.update_message(input, 4, parent.id as _, 1).await?;
+
+        let notification = time::timeout(Duration::from_secs(5), listener.recv()).await??;
+        let payload: serde_json::Value = serde_json::from_str(notification.payload())?;
+        assert_eq!(payload["message"]["id"], parent.id);
+        assert_eq!(payload["message"]["content"], "lunch at noon?");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deleted_message_should_notify_every_member() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "oops".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        let message = state.create_message(input, 4, 3).await?;
+        let mut listener = sqlx::postgres::PgListener::connect_with(&state.pool).await?;
+        listener.listen("chat_message_deleted").await?;
+
+        state.delete_message(4, message.id as _, 3).await?;
+
+        let notification = time::timeout(Duration::from_secs(5), listener.recv()).await??;
+        let payload: serde_json::Value = serde_json::from_str(notification.payload())?;
+        assert_eq!(payload["message_id"], message.id);
+        assert_eq!(payload["members"], serde_json::json!([1, 3, 4]));
+        Ok(())
+    }
+
     fn upload_dummy_file(state: &AppState) -> Result<String> {
         let file = ChatFile::new(1, "test.txt", b"hello world");
         let path = file.path(&state.config.server.base_dir);
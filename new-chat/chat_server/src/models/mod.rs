@@ -1,13 +1,28 @@
 mod agent;
+mod agent_run;
+mod agent_template;
+mod annotation;
 mod chat;
 mod file;
 mod message;
+mod moderation;
+mod reaction;
+mod usage;
 mod user;
 mod workspace;
 
-pub use agent::{CreateAgent, UpdateAgent};
-pub use chat::{AddMembers, CreateChat, UpdateChat};
-pub use message::{CreateMessage, ListMessages};
+pub(crate) use agent::check_agent_host;
+pub use agent::{
+    AgentTestOutput, CreateAgent, TestAgent, TestAgentDefinition, UpdateAgent, mentioned_agents,
+};
+pub use agent_run::{AgentRun, AgentRunStatus};
+pub use agent_template::{
+    AgentTemplate, AgentTemplateVersion, AttachAgent, CreateAgentTemplate, UpdateAgentTemplate,
+};
+pub use chat::{AddMembers, CreateChat, MarkRead, ReadReceipt, UpdateChat};
+pub use message::{CreateMessage, ListMessages, Thread, UpdateMessage};
+pub use moderation::{ModerationAction, ModerationReview, ResolveModerationReview};
+pub use usage::{UsageQuery, UsageTotals, WorkspaceUsage};
 pub use user::{ChangePasswordInput, CreateUser, SigninUser};
 pub use workspace::{CreateInvitation, JoinWorkspace, WorkspaceInvitation};
 
@@ -0,0 +1,466 @@
+use super::{agent::check_agent_host, message::tombstone_message};
+use crate::{AppError, AppState, agent::AgentVariant, error::MessageRejection};
+use chat_core::{AgentContext, AgentDecision, ChatAgent};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use std::time::Instant;
+use tracing::warn;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "moderation_action", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    /// the message wasn't sent
+    Reject,
+    /// the message was sent with parts redacted
+    Redact,
+    /// the message was sent as is
+    Flag,
+}
+
+/// A message a moderation agent acted on, queued for review by the workspace owner
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ModerationReview {
+    pub id: i64,
+    pub ws_id: i64,
+    pub chat_id: i64,
+    /// not set for rejected messages
+    pub message_id: Option<i64>,
+    pub sender_id: i64,
+    pub agent_id: Option<i64>,
+    pub action: ModerationAction,
+    pub reason: String,
+    /// the content as sent by the user, before any redaction
+    pub content: String,
+    pub resolved_by: Option<i64>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ResolveModerationReview {
+    /// delete the message of the review, e.g. a flagged one that shouldn't stay
+    #[serde(default)]
+    pub delete_message: bool,
+}
+
+/// What the moderation agents of a chat decided on a message about to be sent
+#[derive(Debug, Default)]
+pub(crate) struct Moderation {
+    /// the content to send, with the spans redacted by any agent
+    pub content: String,
+    pub findings: Vec<ModerationFinding>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ModerationFinding {
+    pub agent_id: i64,
+    pub agent_name: String,
+    pub action: ModerationAction,
+    pub reason: String,
+}
+
+impl Moderation {
+    /// the finding of the agent that rejected the message, if any
+    pub fn rejection(&self) -> Option<&ModerationFinding> {
+        self.findings
+            .iter()
+            .find(|f| f.action == ModerationAction::Reject)
+    }
+
+    /// queue the findings for review, `message_id` is the message sent if it wasn't
+    /// rejected. Returns the ids of the reviews.
+    pub async fn save(
+        &self,
+        conn: &mut PgConnection,
+        ctx: &AgentContext,
+        original: &str,
+        message_id: Option<i64>,
+    ) -> Result<Vec<i64>, AppError> {
+        let sender_id = ctx.sender.as_ref().map(|u| u.id).unwrap_or_default();
+        let mut ids = Vec::with_capacity(self.findings.len());
+        for finding in &self.findings {
+            let id = sqlx::query_scalar(
+                r#"
+                INSERT INTO moderation_reviews (ws_id, chat_id, message_id, sender_id, agent_id,
+                    action, reason, content)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                RETURNING id
+                "#,
+            )
+            .bind(ctx.ws_id)
+            .bind(ctx.chat_id)
+            .bind(message_id)
+            .bind(sender_id)
+            .bind(finding.agent_id)
+            .bind(finding.action)
+            .bind(&finding.reason)
+            .bind(original)
+            .fetch_one(&mut *conn)
+            .await?;
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+}
+
+impl AppState {
+    /// Run the moderation agents in order on a message about to be sent, each one sees
+    /// the redactions of the previous ones and a rejection stops the others.
+    ///
+    /// Moderation doesn't fail the message: an agent that fails or is over the AI quota
+    /// flags it instead, so a person has a look.
+    pub(crate) async fn moderate_message(
+        &self,
+        agents: &[ChatAgent],
+        content: &str,
+        ctx: &AgentContext,
+    ) -> Moderation {
+        let mut moderation = Moderation {
+            content: content.to_string(),
+            findings: vec![],
+        };
+        for agent in agents {
+            let finding = |action, reason: String| ModerationFinding {
+                agent_id: agent.id,
+                agent_name: agent.name.clone(),
+                action,
+                reason,
+            };
+            if let Err(e) = check_agent_host(&agent.args, &self.config.agent_hosts) {
+                let reason = format!("not moderated: {}", e);
+                moderation
+                    .findings
+                    .push(finding(ModerationAction::Flag, reason));
+                continue;
+            }
+            if let Err(e) = self.consume_ai_request(ctx.ws_id as _).await {
+                let reason = format!("not moderated: {}", e);
+                moderation
+                    .findings
+                    .push(finding(ModerationAction::Flag, reason));
+                continue;
+            }
+
+            let variant = AgentVariant::from(agent.clone());
+            let started = Instant::now();
+            let result = self
+                .with_timeout(variant.process_with_usage(&moderation.content, ctx))
+                .await;
+            let usage = result.as_ref().ok().map(|(_, usage)| *usage);
+            self.track_usage(agent, None, ctx, started, usage).await;
+
+            match result {
+                Ok((AgentDecision::Reject(reason), _)) => {
+                    moderation
+                        .findings
+                        .push(finding(ModerationAction::Reject, reason));
+                    break;
+                }
+                Ok((AgentDecision::Delete, _)) => {
+                    let reason = String::new();
+                    moderation
+                        .findings
+                        .push(finding(ModerationAction::Reject, reason));
+                    break;
+                }
+                Ok((AgentDecision::Redact { content, reason }, _)) => {
+                    moderation.content = content;
+                    moderation
+                        .findings
+                        .push(finding(ModerationAction::Redact, reason));
+                }
+                Ok((AgentDecision::Flag(reason), _)) => {
+                    moderation
+                        .findings
+                        .push(finding(ModerationAction::Flag, reason));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("moderation agent {} failed: {}", agent.name, e);
+                    let reason = format!("moderation failed: {}", e);
+                    moderation
+                        .findings
+                        .push(finding(ModerationAction::Flag, reason));
+                }
+            }
+        }
+        moderation
+    }
+
+    /// Moderate content `user_id` is about to send to a chat, see `moderate_message`.
+    /// If an agent rejects it, the findings are queued for review and it fails with
+    /// `MessageRejected`; otherwise they are saved along with the message.
+    pub(crate) async fn check_message(
+        &self,
+        moderators: &[ChatAgent],
+        chat_id: u64,
+        user_id: u64,
+        content: &str,
+    ) -> Result<(Moderation, AgentContext), AppError> {
+        let ctx = self.load_chat_context(chat_id, user_id as _, None).await?;
+        let moderation = self.moderate_message(moderators, content, &ctx).await;
+        let Some(finding) = moderation.rejection() else {
+            return Ok((moderation, ctx));
+        };
+
+        let mut conn = self.pool.acquire().await?;
+        let ids = moderation.save(&mut conn, &ctx, content, None).await?;
+        // a rejection stops moderation, it's the last finding
+        let review_id = ids.last().copied().unwrap_or_default();
+        Err(AppError::MessageRejected(MessageRejection {
+            agent: finding.agent_name.clone(),
+            reason: finding.reason.clone(),
+            review_id,
+        }))
+    }
+
+    /// the unresolved reviews of a workspace, oldest first
+    pub async fn list_moderation_reviews(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<ModerationReview>, AppError> {
+        self.verify_workspace_owner(ws_id, user_id, "review moderated messages")
+            .await?;
+        let reviews = sqlx::query_as(
+            r#"
+            SELECT * FROM moderation_reviews
+            WHERE ws_id = $1 AND resolved_at IS NULL
+            ORDER BY id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(reviews)
+    }
+
+    /// mark a review resolved by the workspace owner, optionally deleting its message
+    pub async fn resolve_moderation_review(
+        &self,
+        input: ResolveModerationReview,
+        ws_id: u64,
+        id: u64,
+        user_id: u64,
+    ) -> Result<ModerationReview, AppError> {
+        self.verify_workspace_owner(ws_id, user_id, "review moderated messages")
+            .await?;
+        let mut tx = self.pool.begin().await?;
+        let review: ModerationReview = sqlx::query_as(
+            r#"
+            UPDATE moderation_reviews
+            SET resolved_by = $3, resolved_at = NOW()
+            WHERE ws_id = $1 AND id = $2 AND resolved_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("unresolved moderation review id: {}", id)))?;
+
+        if input.delete_message
+            && let Some(message_id) = review.message_id
+        {
+            tombstone_message(&mut tx, message_id).await?;
+        }
+        tx.commit().await?;
+
+        Ok(review)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::AiQuotaLimits,
+        models::{CreateAgent, CreateMessage, UpdateMessage},
+    };
+    use anyhow::Result;
+    use chat_core::{AdapterType, AgentType};
+
+    async fn create_moderator(state: &AppState, name: &str, verdict: &str) -> Result<ChatAgent> {
+        let input = CreateAgent::new(
+            name,
+            AgentType::Moderate,
+            AdapterType::Test,
+            "scripted",
+            "Keep {{chat.name}} friendly",
+            serde_json::json!({ "script": { "mode": "fixed", "reply": verdict } }),
+        );
+        Ok(state.create_agent(input, 1).await?)
+    }
+
+    fn message(content: &str) -> CreateMessage {
+        CreateMessage {
+            content: content.to_string(),
+            files: vec![],
+            parent_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_message_should_not_be_sent() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        create_moderator(
+            &state,
+            "guard",
+            r#"{"action": "reject", "reason": "no ads here"}"#,
+        )
+        .await?;
+
+        let err = state
+            .create_message(message("buy cheap watches"), 1, 2)
+            .await
+            .unwrap_err();
+        let AppError::MessageRejected(rejection) = err else {
+            panic!("message should be rejected: {:?}", err);
+        };
+        assert_eq!(rejection.agent, "guard");
+        assert_eq!(rejection.reason, "no ads here");
+
+        let reviews = state.list_moderation_reviews(1, 1).await?;
+        assert_eq!(reviews.len(), 1);
+        assert_eq!(reviews[0].id, rejection.review_id);
+        assert_eq!(reviews[0].action, ModerationAction::Reject);
+        assert_eq!(reviews[0].message_id, None);
+        assert_eq!(reviews[0].sender_id, 2);
+        assert_eq!(reviews[0].content, "buy cheap watches");
+
+        // edits can't sneak it in either, message 1 is "Hello, world!" of user 1
+        let input = UpdateMessage {
+            content: "buy cheap watches".to_string(),
+        };
+        let err = state.update_message(input, 1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::MessageRejected(_)));
+        let message = state
+            .get_message_by_id(1)
+            .await?
+            .expect("message should exist");
+        assert_eq!(message.content, "Hello, world!");
+        assert_eq!(message.edited_at, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redacted_and_flagged_messages_should_be_reviewed() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        create_moderator(
+            &state,
+            "privacy",
+            r#"{"action": "redact", "spans": ["hunter2"], "reason": "password"}"#,
+        )
+        .await?;
+        create_moderator(&state, "tone", "flag").await?;
+
+        let sent = state
+            .create_message(message("my password is hunter2"), 1, 2)
+            .await?;
+        assert_eq!(sent.content, "my password is [redacted]");
+
+        // only the owner reviews
+        let err = state.list_moderation_reviews(1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        let reviews = state.list_moderation_reviews(1, 1).await?;
+        let actions = reviews
+            .iter()
+            .map(|r| (r.action, r.reason.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            vec![
+                (ModerationAction::Redact, "password"),
+                (ModerationAction::Flag, "")
+            ]
+        );
+        assert!(reviews.iter().all(|r| r.message_id == Some(sent.id)));
+        assert!(
+            reviews
+                .iter()
+                .all(|r| r.content == "my password is hunter2")
+        );
+
+        let input = ResolveModerationReview {
+            delete_message: true,
+        };
+        let review = state
+            .resolve_moderation_review(input, 1, reviews[1].id as _, 1)
+            .await?;
+        assert_eq!(review.resolved_by, Some(1));
+        let message = state
+            .get_message_by_id(sent.id as _)
+            .await?
+            .expect("message should exist");
+        assert!(message.deleted_at.is_some());
+        assert_eq!(message.content, "");
+
+        let reviews = state.list_moderation_reviews(1, 1).await?;
+        assert_eq!(reviews.len(), 1);
+        let err = state
+            .resolve_moderation_review(ResolveModerationReview::default(), 1, review.id as _, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn messages_should_be_flagged_without_quota() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // workspace 3 has its agents turned off
+        let state = state.with_config(|config| {
+            let limits = AiQuotaLimits {
+                requests_per_day: Some(0),
+                ..Default::default()
+            };
+            config
+                .ai_quota
+                .get_or_insert_default()
+                .workspaces
+                .insert(3, limits);
+        });
+        let chat_id: i64 = sqlx::query_scalar(
+            "INSERT INTO chats (ws_id, type, members) VALUES (3, 'group', '{1, 2, 3}') RETURNING id",
+        )
+        .fetch_one(&state.pool)
+        .await?;
+        // its members live in workspace 1
+        sqlx::query("UPDATE workspaces SET owner_id = 1 WHERE id = 3")
+            .execute(&state.pool)
+            .await?;
+        let input = CreateAgent::new(
+            "guard",
+            AgentType::Moderate,
+            AdapterType::Test,
+            "scripted",
+            "",
+            serde_json::json!({ "script": { "mode": "fixed", "reply": "reject" } }),
+        );
+        state.create_agent(input, chat_id as _).await?;
+
+        // sent, without agents
+        let (sent, skipped) = state
+            .send_message(message("buy cheap watches"), chat_id as _, 2)
+            .await?;
+        assert!(skipped.is_some_and(|reason| reason.starts_with("ai quota exceeded: ")));
+
+        let reviews = state.list_moderation_reviews(3, 1).await?;
+        assert_eq!(reviews.len(), 1);
+        assert_eq!(reviews[0].action, ModerationAction::Flag);
+        assert!(reviews[0].reason.starts_with("not moderated: "));
+        assert_eq!(reviews[0].message_id, Some(sent.id));
+        Ok(())
+    }
+}
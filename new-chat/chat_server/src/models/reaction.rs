@@ -0,0 +1,284 @@
+use crate::{AppError, AppState};
+use chat_core::{Message, Reaction};
+use sqlx::FromRow;
+
+/// longest emoji accepted in bytes, enough for sequences with skin tones and joiners
+const MAX_EMOJI_LEN: usize = 64;
+
+#[derive(Debug, FromRow)]
+struct MessageReaction {
+    message_id: i64,
+    #[sqlx(flatten)]
+    reaction: Reaction,
+}
+
+impl AppState {
+    /// react to a message of a chat, reacting twice with the same emoji does nothing.
+    /// Returns the reactions to the message.
+    pub async fn add_reaction(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+        emoji: &str,
+    ) -> Result<Vec<Reaction>, AppError> {
+        validate_emoji(emoji)?;
+        let message = self.get_reactable_message(chat_id, message_id).await?;
+        sqlx::query(
+            r#"
+            INSERT INTO message_reactions (message_id, user_id, emoji)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(message.id)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .execute(&self.pool)
+        .await?;
+
+        self.list_reactions(message.id).await
+    }
+
+    /// take back a reaction to a message of a chat, returns the reactions left
+    pub async fn remove_reaction(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+        emoji: &str,
+    ) -> Result<Vec<Reaction>, AppError> {
+        let message = self.get_reactable_message(chat_id, message_id).await?;
+        sqlx::query(
+            r#"
+            DELETE FROM message_reactions
+            WHERE message_id = $1 AND user_id = $2 AND emoji = $3
+            "#,
+        )
+        .bind(message.id)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .execute(&self.pool)
+        .await?;
+
+        self.list_reactions(message.id).await
+    }
+
+    /// fill in the reactions of messages, the most recent emoji last
+    pub(crate) async fn load_reactions(&self, messages: &mut [Message]) -> Result<(), AppError> {
+        let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+        let reactions: Vec<MessageReaction> = sqlx::query_as(
+            r#"
+            SELECT message_id, emoji, COUNT(*) AS count,
+                array_agg(user_id ORDER BY created_at, user_id) AS user_ids
+            FROM message_reactions
+            WHERE message_id = ANY($1)
+            GROUP BY message_id, emoji
+            ORDER BY MIN(created_at), emoji
+            "#,
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        for r in reactions {
+            if let Some(message) = messages.iter_mut().find(|m| m.id == r.message_id) {
+                message.reactions.push(r.reaction);
+            }
+        }
+        Ok(())
+    }
+
+    async fn list_reactions(&self, message_id: i64) -> Result<Vec<Reaction>, AppError> {
+        let reactions = sqlx::query_as(
+            r#"
+            SELECT emoji, COUNT(*) AS count,
+                array_agg(user_id ORDER BY created_at, user_id) AS user_ids
+            FROM message_reactions
+            WHERE message_id = $1
+            GROUP BY emoji
+            ORDER BY MIN(created_at), emoji
+            "#,
+        )
+        .bind(message_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(reactions)
+    }
+
+    /// a sent message of the chat, not deleted or still being generated
+    async fn get_reactable_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Message, AppError> {
+        let message = self
+            .get_message_by_id(message_id)
+            .await?
+            .filter(|m| m.chat_id == chat_id as i64 && m.deleted_at.is_none())
+            .ok_or_else(|| AppError::NotFound(format!("message id: {} not found", message_id)))?;
+        if message.is_pending {
+            return Err(AppError::ReactionError(format!(
+                "message {} is still being written",
+                message_id
+            )));
+        }
+        Ok(message)
+    }
+}
+
+/// a reaction is a single emoji, not text like "+1"
+fn validate_emoji(emoji: &str) -> Result<(), AppError> {
+    if emoji.is_empty() || emoji.len() > MAX_EMOJI_LEN {
+        return Err(AppError::ReactionError(format!(
+            "emoji should be 1 to {} bytes",
+            MAX_EMOJI_LEN
+        )));
+    }
+    if !is_emoji_sequence(emoji) {
+        return Err(AppError::ReactionError(format!("invalid emoji: {}", emoji)));
+    }
+    Ok(())
+}
+
+/// Whether every char is an emoji, a keycap like "1️⃣", or one of the variation
+/// selector, skin tones (emojis themselves) and zero width joiner between them
+fn is_emoji_sequence(s: &str) -> bool {
+    let mut chars = s.chars().peekable();
+    // whether the next char must be an emoji, i.e. at the start and after a joiner
+    let mut needs_emoji = true;
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{FE0F}' | '\u{200D}' if needs_emoji => return false,
+            '\u{FE0F}' => {}
+            '\u{200D}' => needs_emoji = true,
+            '0'..='9' | '#' | '*' => {
+                chars.next_if_eq(&'\u{FE0F}');
+                if chars.next() != Some('\u{20E3}') {
+                    return false;
+                }
+                needs_emoji = false;
+            }
+            c if is_pictographic(c) => needs_emoji = false,
+            _ => return false,
+        }
+    }
+    !needs_emoji
+}
+
+/// emoji code points, the Extended_Pictographic ones and regional indicators
+fn is_pictographic(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00A9}'
+            | '\u{00AE}'
+            | '\u{203C}'
+            | '\u{2049}'
+            | '\u{2122}'
+            | '\u{2139}'
+            | '\u{2194}'..='\u{2199}'
+            | '\u{21A9}'..='\u{21AA}'
+            | '\u{231A}'..='\u{231B}'
+            | '\u{2328}'
+            | '\u{23CF}'
+            | '\u{23E9}'..='\u{23F3}'
+            | '\u{23F8}'..='\u{23FA}'
+            | '\u{24C2}'
+            | '\u{25AA}'..='\u{25AB}'
+            | '\u{25B6}'
+            | '\u{25C0}'
+            | '\u{25FB}'..='\u{25FE}'
+            | '\u{2600}'..='\u{27BF}'
+            | '\u{2934}'..='\u{2935}'
+            | '\u{2B05}'..='\u{2B07}'
+            | '\u{2B1B}'..='\u{2B1C}'
+            | '\u{2B50}'
+            | '\u{2B55}'
+            | '\u{3030}'
+            | '\u{303D}'
+            | '\u{3297}'
+            | '\u{3299}'
+            // mahjong tiles to symbols and pictographs extended-a, with the regional
+            // indicators and skin tones
+            | '\u{1F000}'..='\u{1FAFF}'
+            | '\u{1FC00}'..='\u{1FFFD}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ListMessages;
+    use anyhow::Result;
+
+    #[test]
+    fn validate_emoji_should_reject_text() {
+        assert!(validate_emoji("👍").is_ok());
+        assert!(validate_emoji("👍🏽").is_ok());
+        assert!(validate_emoji("1️⃣").is_ok());
+        assert!(validate_emoji("+1").is_err());
+        assert!(validate_emoji("").is_err());
+        assert!(validate_emoji("👍 👍").is_err());
+        assert!(validate_emoji(&"👍".repeat(20)).is_err());
+        assert!(validate_emoji("❤️").is_ok());
+        assert!(validate_emoji("🇫🇷").is_ok());
+        assert!(validate_emoji("#️⃣").is_ok());
+        assert!(validate_emoji("👩🏽‍💻").is_ok());
+        assert!(validate_emoji("👨‍👩‍👧").is_ok());
+        assert!(validate_emoji("héllo").is_err());
+        assert!(validate_emoji("日本語").is_err());
+        assert!(validate_emoji("👍é").is_err());
+        assert!(validate_emoji("1").is_err());
+        assert!(validate_emoji("\u{200D}👍").is_err());
+        assert!(validate_emoji("👍\u{200D}").is_err());
+        assert!(validate_emoji("\u{FE0F}").is_err());
+    }
+
+    #[tokio::test]
+    async fn reactions_should_be_counted_per_emoji() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.add_reaction(1, 1, 2, "👍").await?;
+        state.add_reaction(1, 1, 3, "🎉").await?;
+        // reacting twice counts once
+        state.add_reaction(1, 1, 3, "👍").await?;
+        let reactions = state.add_reaction(1, 1, 3, "👍").await?;
+        assert_eq!(reactions.len(), 2);
+        assert_eq!(reactions[0].emoji, "👍");
+        assert_eq!(reactions[0].count, 2);
+        assert_eq!(reactions[0].user_ids, [2, 3]);
+        assert_eq!(reactions[1].emoji, "🎉");
+
+        let reactions = state.remove_reaction(1, 1, 2, "👍").await?;
+        assert_eq!(reactions[0].user_ids, [3]);
+        let reactions = state.remove_reaction(1, 1, 3, "🎉").await?;
+        assert_eq!(reactions.len(), 1);
+
+        let input = ListMessages {
+            last_id: None,
+            limit: 0,
+        };
+        let messages = state.list_messages(input, 1).await?;
+        let message = messages.iter().find(|m| m.id == 1).unwrap();
+        assert_eq!(message.reactions, reactions);
+        assert!(
+            messages
+                .iter()
+                .filter(|m| m.id != 1)
+                .all(|m| m.reactions.is_empty())
+        );
+
+        // the message must be in the chat
+        let err = state.add_reaction(2, 1, 2, "👍").await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        let err = state.add_reaction(1, 1, 2, "+1").await.unwrap_err();
+        assert!(matches!(err, AppError::ReactionError(_)));
+
+        // deleted messages lose their reactions
+        state.delete_message(1, 1, 1).await?;
+        assert!(state.list_reactions(1).await?.is_empty());
+        let err = state.add_reaction(1, 1, 2, "👍").await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{AppError, AppState};
+use ai_sdk::Usage;
+use chat_core::ChatAgent;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+
+/// time range of the usage to sum up, open ended when not set
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct UsageQuery {
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Usage totals of an agent, or of a whole workspace when `agent_name` isn't set.
+/// An agent deleted since keeps its name but loses its id.
+#[derive(Debug, Clone, Default, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct UsageTotals {
+    pub agent_id: Option<i64>,
+    pub agent_name: Option<String>,
+    pub requests: i64,
+    pub failures: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub total_tokens: i64,
+    pub avg_latency_ms: i64,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct WorkspaceUsage {
+    pub ws_id: i64,
+    pub total: UsageTotals,
+    /// most expensive agents first
+    pub agents: Vec<UsageTotals>,
+}
+
+impl AppState {
+    /// record one call of `agent`, failed calls count as requests without tokens
+    pub async fn record_agent_usage(
+        &self,
+        agent: &ChatAgent,
+        message_id: Option<u64>,
+        usage: Usage,
+        latency: Duration,
+        succeeded: bool,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO agent_usage (ws_id, chat_id, agent_id, agent_name, message_id, model,
+                prompt_tokens, completion_tokens, latency_ms, succeeded)
+            SELECT ws_id, id, $2, $3, $4, $5, $6, $7, $8, $9
+            FROM chats
+            WHERE id = $1
+            "#,
+        )
+        .bind(agent.chat_id)
+        // agents tried before they are created have no id
+        .bind((agent.id > 0).then_some(agent.id))
+        .bind(&agent.name)
+        .bind(message_id.map(|id| id as i64))
+        .bind(&agent.model)
+        .bind(usage.prompt_tokens as i32)
+        .bind(usage.completion_tokens as i32)
+        .bind(latency.as_millis().min(i32::MAX as u128) as i32)
+        .bind(succeeded)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// usage of the agents of a workspace in the time range, per agent and in total
+    pub async fn get_workspace_usage(
+        &self,
+        ws_id: u64,
+        input: &UsageQuery,
+    ) -> Result<WorkspaceUsage, AppError> {
+        let agents: Vec<UsageTotals> = sqlx::query_as(
+            r#"
+            SELECT agent_id, agent_name,
+                COUNT(*) AS requests,
+                COUNT(*) FILTER (WHERE NOT succeeded) AS failures,
+                SUM(prompt_tokens)::BIGINT AS prompt_tokens,
+                SUM(completion_tokens)::BIGINT AS completion_tokens,
+                SUM(prompt_tokens + completion_tokens)::BIGINT AS total_tokens,
+                AVG(latency_ms)::BIGINT AS avg_latency_ms
+            FROM agent_usage
+            WHERE ws_id = $1
+            AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR created_at < $3)
+            GROUP BY agent_id, agent_name
+            ORDER BY total_tokens DESC, agent_name ASC
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(input.since)
+        .bind(input.until)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut total = UsageTotals::default();
+        let mut latency_ms = 0;
+        for agent in &agents {
+            total.requests += agent.requests;
+            total.failures += agent.failures;
+            total.prompt_tokens += agent.prompt_tokens;
+            total.completion_tokens += agent.completion_tokens;
+            total.total_tokens += agent.total_tokens;
+            latency_ms += agent.avg_latency_ms * agent.requests;
+        }
+        if total.requests > 0 {
+            total.avg_latency_ms = latency_ms / total.requests;
+        }
+
+        Ok(WorkspaceUsage {
+            ws_id: ws_id as _,
+            total,
+            agents,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn workspace_usage_should_sum_per_agent() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let agent = state.list_agents(1).await?.remove(0);
+        let usage = Usage {
+            prompt_tokens: 10,
+            completion_tokens: 5,
+        };
+        for latency in [100, 300] {
+            state
+                .record_agent_usage(&agent, Some(1), usage, Duration::from_millis(latency), true)
+                .await?;
+        }
+        state
+            .record_agent_usage(
+                &agent,
+                Some(1),
+                Usage::default(),
+                Duration::from_millis(200),
+                false,
+            )
+            .await?;
+
+        let ws_usage = state.get_workspace_usage(1, &UsageQuery::default()).await?;
+        assert_eq!(ws_usage.agents.len(), 1);
+        let totals = &ws_usage.agents[0];
+        assert_eq!(totals.agent_id, Some(agent.id));
+        assert_eq!(totals.agent_name.as_deref(), Some("translation"));
+        assert_eq!(totals.requests, 3);
+        assert_eq!(totals.failures, 1);
+        assert_eq!(totals.total_tokens, 30);
+        assert_eq!(totals.avg_latency_ms, 200);
+        assert_eq!(ws_usage.total.requests, 3);
+        assert_eq!(ws_usage.total.prompt_tokens, 20);
+        assert_eq!(ws_usage.total.completion_tokens, 10);
+
+        // nothing in the future, nor in another workspace
+        let input = UsageQuery {
+            since: Some(Utc::now() + chrono::Duration::hours(1)),
+            until: None,
+        };
+        let ws_usage = state.get_workspace_usage(1, &input).await?;
+        assert!(ws_usage.agents.is_empty());
+        assert_eq!(ws_usage.total.requests, 0);
+        let ws_usage = state.get_workspace_usage(2, &UsageQuery::default()).await?;
+        assert!(ws_usage.agents.is_empty());
+        Ok(())
+    }
+}
@@ -55,7 +55,7 @@ impl AppState {
     pub async fn find_user_by_id(&self, id: i64) -> Result<Option<User>, AppError> {
         let user = sqlx::query_as(
             "
-            SELECT id, ws_id, fullname, email, created_at
+            SELECT id, ws_id, fullname, email, is_bot, created_at
             FROM users
             WHERE id = $1
             ",
@@ -231,6 +231,10 @@ fn hash_password(password: &str) -> Result<String, AppError> {
 }
 
 fn verify_password(password: &str, password_hash: &str) -> Result<bool, AppError> {
+    // bot users have no password to sign in with
+    if password_hash.is_empty() {
+        return Ok(false);
+    }
     let argon2 = Argon2::default();
     let password_hash = PasswordHash::new(password_hash)?;
 
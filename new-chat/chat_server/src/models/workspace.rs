@@ -94,6 +94,27 @@ impl AppState {
         Ok(ws)
     }
 
+    /// fails with `PermissionDenied` unless `user_id` owns the workspace, `action` is
+    /// what only the owner can do
+    pub(crate) async fn verify_workspace_owner(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+        action: &str,
+    ) -> Result<(), AppError> {
+        let ws = self
+            .find_workspace_by_id(ws_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("workspace id: {} not found", ws_id)))?;
+        if ws.owner_id != user_id as i64 {
+            return Err(AppError::PermissionDenied(format!(
+                "only the workspace owner can {}",
+                action
+            )));
+        }
+        Ok(())
+    }
+
     #[allow(dead_code)]
     pub async fn fetch_chat_users(&self, id: u64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
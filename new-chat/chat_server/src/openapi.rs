@@ -1,13 +1,20 @@
 use crate::{
     AppState, AuthOutput,
-    error::ErrorOutput,
+    error::{ErrorOutput, MessageRejection},
     handlers::*,
     models::{
-        ChatFile, CreateAgent, CreateChat, CreateMessage, ListMessages, SigninUser, UpdateAgent,
+        AgentTemplate, AgentTemplateVersion, AgentTestOutput, AttachAgent, ChatFile, CreateAgent,
+        CreateAgentTemplate, CreateChat, CreateMessage, ListMessages, MarkRead, ModerationAction,
+        ModerationReview, ReadReceipt, ResolveModerationReview, SigninUser, TestAgent,
+        TestAgentDefinition, Thread, UpdateAgent, UpdateAgentTemplate, UpdateMessage, UsageQuery,
+        UsageTotals, WorkspaceUsage,
     },
 };
 use axum::Router;
-use chat_core::{AgentType, Chat, ChatAgent, ChatType, ChatUser, Message, User, Workspace};
+use chat_core::{
+    AgentArgs, AgentOverrides, AgentType, Chat, ChatAgent, ChatType, ChatUser, Message,
+    MessageAnnotation, MessageEdit, Reaction, User, Workspace,
+};
 use utoipa::{
     Modify, OpenApi,
     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
@@ -28,15 +35,32 @@ pub(crate) trait OpenApiRouter {
         list_chat_handler,
         create_chat_handler,
         get_chat_handler,
+        mark_chat_read_handler,
         send_message_handler,
         list_chat_users_handler,
+        get_workspace_usage_handler,
         list_message_handler,
+        list_annotation_handler,
+        update_message_handler,
+        list_message_edits_handler,
+        list_thread_handler,
+        add_reaction_handler,
+        remove_reaction_handler,
         create_agent_handler,
         update_agent_handler,
-        list_agent_handler
+        list_agent_handler,
+        attach_agent_handler,
+        test_agent_handler,
+        test_agent_definition_handler,
+        list_agent_template_handler,
+        create_agent_template_handler,
+        update_agent_template_handler,
+        list_agent_template_versions_handler,
+        list_moderation_reviews_handler,
+        resolve_moderation_review_handler
     ),
     components(schemas(AuthOutput, Chat, ChatType, ChatUser, ChatFile, CreateChat, ChatUser, Message, CreateMessage,
-        ListMessages, SigninUser, User, Workspace, ErrorOutput, CreateAgent, UpdateAgent, ChatAgent, AgentType, ErrorOutput)),
+        ListMessages, MessageAnnotation, MessageEdit, Reaction, UpdateMessage, Thread, MarkRead, ReadReceipt, SigninUser, User, Workspace, ErrorOutput, CreateAgent, UpdateAgent, ChatAgent, AgentArgs, AgentType, AgentOverrides, AgentTemplate, AgentTemplateVersion, AttachAgent, AgentTestOutput, TestAgent, TestAgentDefinition, CreateAgentTemplate, UpdateAgentTemplate, UsageQuery, UsageTotals, WorkspaceUsage, ModerationAction, ModerationReview, ResolveModerationReview, MessageRejection, ErrorOutput)),
     modifiers(&SecurityAddon),
     tags(
         (name = "Chat", description = "Chat related operations")
@@ -0,0 +1,398 @@
+use crate::{AppError, AppState, config::AiQuotaLimits};
+use chrono::{DateTime, Utc};
+use tracing::warn;
+
+/// Lua script for atomic quota accounting of a workspace
+/// KEYS: requests of the day, tokens of the month
+/// ARGV: max requests, max tokens (negative for unlimited), requests to add,
+///       tokens to add, ttl of the requests key, ttl of the tokens key
+/// Returns: (is_allowed, requests, tokens)
+///
+/// Requests are only added when the quota isn't exceeded yet, tokens are always
+/// added since they have already been spent.
+const AI_QUOTA_LUA_SCRIPT: &str = r#"
+local requests = tonumber(redis.call('GET', KEYS[1]) or '0')
+local tokens = tonumber(redis.call('GET', KEYS[2]) or '0')
+local max_requests = tonumber(ARGV[1])
+local max_tokens = tonumber(ARGV[2])
+local add_requests = tonumber(ARGV[3])
+local add_tokens = tonumber(ARGV[4])
+
+local allowed = 1
+if max_requests >= 0 and requests >= max_requests then
+    allowed = 0
+end
+if max_tokens >= 0 and tokens >= max_tokens then
+    allowed = 0
+end
+
+if allowed == 1 and add_requests > 0 then
+    requests = redis.call('INCRBY', KEYS[1], add_requests)
+    if redis.call('TTL', KEYS[1]) < 0 then
+        redis.call('EXPIRE', KEYS[1], ARGV[5])
+    end
+end
+
+if add_tokens > 0 then
+    tokens = redis.call('INCRBY', KEYS[2], add_tokens)
+    if redis.call('TTL', KEYS[2]) < 0 then
+        redis.call('EXPIRE', KEYS[2], ARGV[6])
+    end
+end
+
+return {allowed, requests, tokens}
+"#;
+
+// counters outlive their period a bit, so a late increment doesn't restart them
+const REQUESTS_TTL_SECS: u64 = 2 * 24 * 3600;
+const TOKENS_TTL_SECS: u64 = 32 * 24 * 3600;
+
+#[derive(Debug, Clone, PartialEq)]
+struct AiQuotaResult {
+    allowed: bool,
+    requests: u64,
+    tokens: u64,
+}
+
+/// Counters are kept in redis, when it can't be reached the quota isn't enforced
+/// (fails open) so the agents don't go down with it, a warning is logged instead.
+impl AppState {
+    /// Fails with `AiQuotaExceeded` if the workspace used up its AI quota, without
+    /// counting anything.
+    pub async fn check_ai_quota(&self, ws_id: u64) -> Result<(), AppError> {
+        self.update_ai_quota(ws_id, 0, 0).await
+    }
+
+    /// Count an agent call of the workspace, fails with `AiQuotaExceeded` and doesn't
+    /// count it if the quota is used up.
+    pub async fn consume_ai_request(&self, ws_id: u64) -> Result<(), AppError> {
+        self.update_ai_quota(ws_id, 1, 0).await
+    }
+
+    /// Count the tokens an agent call of the workspace used
+    pub async fn record_ai_tokens(&self, ws_id: u64, tokens: u32) -> Result<(), AppError> {
+        match self.update_ai_quota(ws_id, 0, tokens as u64).await {
+            Err(AppError::AiQuotaExceeded(_)) => Ok(()),
+            ret => ret,
+        }
+    }
+
+    async fn update_ai_quota(
+        &self,
+        ws_id: u64,
+        add_requests: u64,
+        add_tokens: u64,
+    ) -> Result<(), AppError> {
+        let Some(config) = &self.config.ai_quota else {
+            return Ok(());
+        };
+        let limits = config.limits(ws_id as _);
+        if limits == AiQuotaLimits::default() {
+            return Ok(());
+        }
+        // a limit of 0 turns the agents of the workspace off, there's nothing to count
+        if limits.requests_per_day == Some(0) || limits.tokens_per_month == Some(0) {
+            let result = AiQuotaResult {
+                allowed: false,
+                requests: 0,
+                tokens: 0,
+            };
+            return Err(AppError::AiQuotaExceeded(quota_message(
+                ws_id, &limits, &result,
+            )));
+        }
+        let Some(redis) = &self.redis else {
+            return Ok(());
+        };
+
+        let (requests_key, tokens_key) = quota_keys(ws_id, Utc::now());
+        let script = redis::Script::new(AI_QUOTA_LUA_SCRIPT);
+        let ret: Result<Vec<i64>, AppError> = async {
+            let mut conn = redis.0.get().await?;
+            let ret = script
+                .key(&requests_key)
+                .key(&tokens_key)
+                .arg(&quota_args(&limits, add_requests, add_tokens))
+                .invoke_async(&mut conn)
+                .await
+                .map_err(|e| anyhow::anyhow!("ai quota script failed: {}", e))?;
+            Ok(ret)
+        }
+        .await;
+        // an unavailable redis shouldn't take the agents down with it
+        let result = match ret {
+            Ok(ret) => AiQuotaResult::from(ret),
+            Err(e) => {
+                warn!("ai quota of workspace {} not checked: {}", ws_id, e);
+                return Ok(());
+            }
+        };
+
+        if result.allowed {
+            Ok(())
+        } else {
+            Err(AppError::AiQuotaExceeded(quota_message(
+                ws_id, &limits, &result,
+            )))
+        }
+    }
+}
+
+/// ARGV of the quota script
+fn quota_args(limits: &AiQuotaLimits, add_requests: u64, add_tokens: u64) -> [i64; 6] {
+    [
+        limits.requests_per_day.map_or(-1, |n| n as i64),
+        limits.tokens_per_month.map_or(-1, |n| n as i64),
+        add_requests as i64,
+        add_tokens as i64,
+        REQUESTS_TTL_SECS as i64,
+        TOKENS_TTL_SECS as i64,
+    ]
+}
+
+/// what the quota script returns
+impl From<Vec<i64>> for AiQuotaResult {
+    fn from(ret: Vec<i64>) -> Self {
+        Self {
+            allowed: ret[0] == 1,
+            requests: ret[1] as u64,
+            tokens: ret[2] as u64,
+        }
+    }
+}
+
+/// the counters of the current day and month (UTC)
+fn quota_keys(ws_id: u64, now: DateTime<Utc>) -> (String, String) {
+    (
+        format!("ai_quota:{}:requests:{}", ws_id, now.format("%Y%m%d")),
+        format!("ai_quota:{}:tokens:{}", ws_id, now.format("%Y%m")),
+    )
+}
+
+fn quota_message(ws_id: u64, limits: &AiQuotaLimits, result: &AiQuotaResult) -> String {
+    match limits.requests_per_day {
+        Some(max) if result.requests >= max => format!(
+            "workspace {} used {} of {} agent requests today",
+            ws_id, result.requests, max
+        ),
+        _ => format!(
+            "workspace {} used {} of {} agent tokens this month",
+            ws_id,
+            result.tokens,
+            limits.tokens_per_month.unwrap_or_default()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{AiQuotaConfig, RedisConfig},
+        redis::RedisPool,
+    };
+    use chrono::TimeZone;
+    use std::sync::Arc;
+
+    #[test]
+    fn quota_config_should_support_workspace_limits() {
+        let config: AiQuotaConfig = serde_yaml::from_str(
+            r#"
+            requests_per_day: 100
+            workspaces:
+              2:
+                tokens_per_month: 5000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.limits(1),
+            AiQuotaLimits {
+                requests_per_day: Some(100),
+                tokens_per_month: None
+            }
+        );
+        assert_eq!(
+            config.limits(2),
+            AiQuotaLimits {
+                requests_per_day: None,
+                tokens_per_month: Some(5000)
+            }
+        );
+    }
+
+    #[test]
+    fn quota_keys_should_be_per_day_and_month() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 18, 9, 30, 0).unwrap();
+        assert_eq!(
+            quota_keys(1, now),
+            (
+                "ai_quota:1:requests:20261018".to_string(),
+                "ai_quota:1:tokens:202610".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn quota_message_should_name_the_exceeded_limit() {
+        let limits = AiQuotaLimits {
+            requests_per_day: Some(10),
+            tokens_per_month: Some(1000),
+        };
+        let result = AiQuotaResult {
+            allowed: false,
+            requests: 3,
+            tokens: 1200,
+        };
+        assert_eq!(
+            quota_message(1, &limits, &result),
+            "workspace 1 used 1200 of 1000 agent tokens this month"
+        );
+        let result = AiQuotaResult {
+            allowed: false,
+            requests: 10,
+            tokens: 0,
+        };
+        assert_eq!(
+            quota_message(1, &limits, &result),
+            "workspace 1 used 10 of 10 agent requests today"
+        );
+    }
+
+    /// Runs the quota script on a fake redis, a Lua table with the commands the
+    /// script calls
+    struct FakeRedis(mlua::Lua);
+
+    impl FakeRedis {
+        fn new() -> Self {
+            let lua = mlua::Lua::new();
+            lua.load(
+                r#"
+                store, ttls = {}, {}
+                redis = {}
+                function redis.call(cmd, key, arg)
+                    if cmd == 'GET' then
+                        return store[key] and tostring(store[key]) or false
+                    elseif cmd == 'INCRBY' then
+                        store[key] = (store[key] or 0) + tonumber(arg)
+                        return store[key]
+                    elseif cmd == 'TTL' then
+                        return ttls[key] or -1
+                    elseif cmd == 'EXPIRE' then
+                        ttls[key] = tonumber(arg)
+                        return 1
+                    end
+                    error('unexpected command ' .. cmd)
+                end
+                "#,
+            )
+            .exec()
+            .unwrap();
+            Self(lua)
+        }
+
+        fn run(&self, limits: &AiQuotaLimits, add_requests: u64, add_tokens: u64) -> AiQuotaResult {
+            let globals = self.0.globals();
+            globals.set("KEYS", ["requests", "tokens"]).unwrap();
+            // redis passes ARGV as strings
+            let args = quota_args(limits, add_requests, add_tokens).map(|arg| arg.to_string());
+            globals.set("ARGV", args).unwrap();
+            let ret: Vec<i64> = self.0.load(AI_QUOTA_LUA_SCRIPT).eval().unwrap();
+            AiQuotaResult::from(ret)
+        }
+
+        fn ttl(&self, key: &str) -> Option<i64> {
+            let ttls: mlua::Table = self.0.globals().get("ttls").unwrap();
+            ttls.get(key).unwrap()
+        }
+    }
+
+    #[test]
+    fn quota_script_should_enforce_limits() {
+        let redis = FakeRedis::new();
+        let limits = AiQuotaLimits {
+            requests_per_day: Some(2),
+            tokens_per_month: Some(100),
+        };
+        let allowed = |requests, tokens| AiQuotaResult {
+            allowed: true,
+            requests,
+            tokens,
+        };
+        assert_eq!(redis.run(&limits, 1, 0), allowed(1, 0));
+        assert_eq!(redis.ttl("requests"), Some(REQUESTS_TTL_SECS as i64));
+        assert_eq!(redis.run(&limits, 1, 0), allowed(2, 0));
+        // over the limit, the request isn't counted
+        let denied = AiQuotaResult {
+            allowed: false,
+            requests: 2,
+            tokens: 0,
+        };
+        assert_eq!(redis.run(&limits, 1, 0), denied);
+        assert_eq!(redis.run(&limits, 0, 0), denied);
+
+        // spent tokens are always counted, even over the limit
+        let redis = FakeRedis::new();
+        assert_eq!(redis.run(&limits, 0, 150), allowed(0, 150));
+        assert_eq!(redis.ttl("tokens"), Some(TOKENS_TTL_SECS as i64));
+        let result = redis.run(&limits, 1, 10);
+        assert!(!result.allowed);
+        assert_eq!((result.requests, result.tokens), (0, 160));
+
+        // no limits
+        let redis = FakeRedis::new();
+        let unlimited = AiQuotaLimits::default();
+        for n in 1..=3 {
+            assert_eq!(redis.run(&unlimited, 1, 1000), allowed(n, 1000 * n));
+        }
+    }
+
+    #[tokio::test]
+    async fn ai_quota_should_fail_open_without_redis() -> anyhow::Result<()> {
+        let (_tdb, mut state) = AppState::new_for_test().await?;
+        let inner = Arc::get_mut(&mut state.inner).expect("state shouldn't be shared yet");
+        // nothing listens there, connections are refused
+        let config = RedisConfig {
+            url: "redis://127.0.0.1:1".to_string(),
+            pool_size: 1,
+        };
+        inner.redis = Some(RedisPool::new(&config).await?);
+        inner.config.ai_quota = Some(AiQuotaConfig {
+            default: AiQuotaLimits {
+                requests_per_day: Some(1),
+                tokens_per_month: None,
+            },
+            workspaces: Default::default(),
+        });
+        for _ in 0..3 {
+            state.consume_ai_request(1).await?;
+        }
+        state.check_ai_quota(1).await?;
+        state.record_ai_tokens(1, 10).await?;
+        Ok(())
+    }
+
+    // needs the redis server of chat.yaml to be reachable
+    #[ignore]
+    #[tokio::test]
+    async fn ai_quota_should_be_enforced() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // a workspace id no fixture uses, so runs don't see each other's counters
+        let ws_id = 1000 + (Utc::now().timestamp_subsec_nanos() as u64 % 100_000);
+        let max = state
+            .config
+            .ai_quota
+            .as_ref()
+            .and_then(|c| c.limits(ws_id as _).requests_per_day)
+            .expect("requests_per_day should be configured");
+        for _ in 0..max {
+            state.consume_ai_request(ws_id).await?;
+        }
+        let err = state.consume_ai_request(ws_id).await.unwrap_err();
+        assert!(matches!(err, AppError::AiQuotaExceeded(_)));
+        assert!(state.check_ai_quota(ws_id).await.is_err());
+        // spent tokens are still counted
+        state.record_ai_tokens(ws_id, 10).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+use crate::{AppError, AppState, models::ChatFile};
+use ai_sdk::{AiAdapter, AiService, Completion, Message, Tool, ToolCall, Usage};
+use chat_core::{AgentContext, AgentError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::str::FromStr;
+use tokio::fs;
+
+/// rounds of tool calls before the model has to answer
+const MAX_TOOL_ROUNDS: usize = 5;
+const MAX_SEARCH_RESULTS: i64 = 50;
+/// bytes of a file handed to the model
+const MAX_FILE_TEXT: usize = 16 * 1024;
+
+/// Server-side tools an agent may call, enabled per agent in `args.tools`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatTool {
+    SearchMessages,
+    ListMembers,
+    FetchFileText,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchMessagesArgs {
+    query: String,
+    #[serde(default)]
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FetchFileTextArgs {
+    url: String,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct FoundMessage {
+    id: i64,
+    sender_id: i64,
+    content: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl ChatTool {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChatTool::SearchMessages => "search_messages",
+            ChatTool::ListMembers => "list_members",
+            ChatTool::FetchFileText => "fetch_file_text",
+        }
+    }
+
+    pub fn definition(&self) -> Tool {
+        let (description, parameters) = match self {
+            ChatTool::SearchMessages => (
+                "Search the messages of this chat, newest first",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "query": { "type": "string", "description": "text to look for" },
+                        "limit": { "type": "integer", "description": "max number of messages, 10 by default" }
+                    },
+                    "required": ["query"]
+                }),
+            ),
+            ChatTool::ListMembers => (
+                "List the members of this chat",
+                json!({ "type": "object", "properties": {} }),
+            ),
+            ChatTool::FetchFileText => (
+                "Fetch the text of a file attached to a message, by its url (/files/...)",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "url": { "type": "string", "description": "file url" }
+                    },
+                    "required": ["url"]
+                }),
+            ),
+        };
+        Tool {
+            name: self.name().to_string(),
+            description: description.to_string(),
+            parameters,
+        }
+    }
+}
+
+impl FromStr for ChatTool {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "search_messages" => Ok(ChatTool::SearchMessages),
+            "list_members" => Ok(ChatTool::ListMembers),
+            "fetch_file_text" => Ok(ChatTool::FetchFileText),
+            _ => Err(AppError::ToolError(format!("unknown tool: {}", s))),
+        }
+    }
+}
+
+impl AppState {
+    /// Complete `messages`, running the tool calls the model asks for until it answers.
+    /// The usage of the answer covers all the rounds.
+    pub async fn complete_with_tools(
+        &self,
+        adapter: &AiAdapter,
+        mut messages: Vec<Message>,
+        tools: &[ChatTool],
+        ctx: &AgentContext,
+    ) -> Result<Completion, AppError> {
+        let definitions = tools.iter().map(ChatTool::definition).collect::<Vec<_>>();
+        let mut usage = Usage::default();
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let mut completion = self
+                .with_timeout(async {
+                    Ok::<_, AgentError>(adapter.complete_with_tools(&messages, &definitions).await?)
+                })
+                .await?;
+            usage += completion.usage;
+            if completion.tool_calls.is_empty() {
+                completion.usage = usage;
+                return Ok(completion);
+            }
+
+            let calls = completion.tool_calls.clone();
+            messages.push(completion.into());
+            for call in &calls {
+                // errors go back to the model, so it can fix its arguments
+                let result = match self.call_tool(call, tools, ctx).await {
+                    Ok(result) => result,
+                    Err(e) => format!("error: {}", e),
+                };
+                messages.push(Message::tool(call, result));
+            }
+        }
+
+        // out of rounds, the model has to answer with what it has
+        let mut completion = self
+            .with_timeout(async { Ok::<_, AgentError>(adapter.complete(&messages).await?) })
+            .await?;
+        completion.usage += usage;
+        Ok(completion)
+    }
+
+    /// run a tool call for an agent processing a message in `ctx`, if the tool is enabled
+    pub async fn call_tool(
+        &self,
+        call: &ToolCall,
+        tools: &[ChatTool],
+        ctx: &AgentContext,
+    ) -> Result<String, AppError> {
+        let tool = ChatTool::from_str(&call.name)?;
+        if !tools.contains(&tool) {
+            return Err(AppError::ToolError(format!(
+                "tool {} is not enabled",
+                call.name
+            )));
+        }
+
+        match tool {
+            ChatTool::SearchMessages => {
+                let args: SearchMessagesArgs = tool_args(call)?;
+                // an empty pattern would match every message of the chat
+                if args.query.trim().is_empty() {
+                    return Err(AppError::ToolError(
+                        "query for search_messages must not be empty".to_string(),
+                    ));
+                }
+                let limit = args.limit.unwrap_or(10).clamp(1, MAX_SEARCH_RESULTS);
+                let pattern = format!(
+                    "%{}%",
+                    args.query
+                        .replace('\\', "\\\\")
+                        .replace('%', "\\%")
+                        .replace('_', "\\_")
+                );
+                let messages: Vec<FoundMessage> = sqlx::query_as(
+                    r#"
+                    SELECT id, sender_id, COALESCE(modified_content, content) AS content, created_at
+                    FROM messages
+                    WHERE chat_id = $1 AND is_pending = FALSE AND deleted_at IS NULL
+                    AND (content ILIKE $2 OR modified_content ILIKE $2)
+                    ORDER BY id DESC
+                    LIMIT $3
+                    "#,
+                )
+                .bind(ctx.chat_id)
+                .bind(pattern)
+                .bind(limit)
+                .fetch_all(&self.pool)
+                .await?;
+                Ok(json!(messages).to_string())
+            }
+            ChatTool::ListMembers => Ok(json!(ctx.members).to_string()),
+            ChatTool::FetchFileText => {
+                let args: FetchFileTextArgs = tool_args(call)?;
+                let file = ChatFile::from_str(&args.url)?;
+                let ws_id = ctx.sender.as_ref().map(|u| u.ws_id as u64);
+                let path = file.path(&self.config.server.base_dir);
+                // files of other workspaces don't exist for this chat
+                if ws_id != Some(file.ws_id) || !path.exists() {
+                    return Err(AppError::NotFound(format!(
+                        "file {} doesn't exist",
+                        args.url
+                    )));
+                }
+                let data = fs::read(path).await?;
+                let mut text = String::from_utf8(data)
+                    .map_err(|_| AppError::ToolError(format!("{} is not a text file", args.url)))?;
+                if text.len() > MAX_FILE_TEXT {
+                    let end = text.floor_char_boundary(MAX_FILE_TEXT);
+                    text.truncate(end);
+                }
+                Ok(text)
+            }
+        }
+    }
+}
+
+fn tool_args<T: for<'de> Deserialize<'de>>(call: &ToolCall) -> Result<T, AppError> {
+    serde_json::from_value(call.arguments.clone())
+        .map_err(|e| AppError::ToolError(format!("invalid arguments for {}: {}", call.name, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ListMessages;
+    use anyhow::Result;
+
+    async fn context(state: &AppState) -> Result<AgentContext> {
+        let input = ListMessages {
+            last_id: None,
+            limit: 1,
+        };
+        let message = state.list_messages(input, 1).await?.remove(0);
+        Ok(state.load_agent_context(&message).await?)
+    }
+
+    fn tool_call(name: &str, arguments: serde_json::Value) -> ToolCall {
+        ToolCall {
+            id: "call_0".to_string(),
+            name: name.to_string(),
+            arguments,
+        }
+    }
+
+    #[tokio::test]
+    async fn search_messages_tool_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ctx = context(&state).await?;
+        let tools = [ChatTool::SearchMessages];
+
+        let call = tool_call("search_messages", json!({ "query": "how ARE", "limit": 1 }));
+        let result = state.call_tool(&call, &tools, &ctx).await?;
+        let found: Vec<serde_json::Value> = serde_json::from_str(&result)?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0]["content"], "How are you?");
+        assert_eq!(found[0]["sender_id"], 3);
+
+        // % is matched literally
+        let call = tool_call("search_messages", json!({ "query": "%" }));
+        assert_eq!(state.call_tool(&call, &tools, &ctx).await?, "[]");
+
+        // deleted messages are gone for the agent too
+        let id = found[0]["id"].as_u64().expect("id should be a number");
+        state.delete_message(1, id, 3).await?;
+        let call = tool_call("search_messages", json!({ "query": "how are you" }));
+        let result = state.call_tool(&call, &tools, &ctx).await?;
+        let found: Vec<serde_json::Value> = serde_json::from_str(&result)?;
+        assert_eq!(found.len(), 1);
+        assert_ne!(found[0]["id"], id);
+
+        for query in ["", "  \t"] {
+            let call = tool_call("search_messages", json!({ "query": query }));
+            let err = state.call_tool(&call, &tools, &ctx).await.unwrap_err();
+            assert!(matches!(err, AppError::ToolError(_)));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tools_should_be_enabled_per_agent() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ctx = context(&state).await?;
+
+        let list = tool_call("list_members", json!({}));
+        let result = state
+            .call_tool(&list, &[ChatTool::ListMembers], &ctx)
+            .await?;
+        let members: Vec<serde_json::Value> = serde_json::from_str(&result)?;
+        assert_eq!(members.len(), 5);
+
+        let err = state
+            .call_tool(&list, &[ChatTool::SearchMessages], &ctx)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "tool error: tool list_members is not enabled"
+        );
+
+        let err = state
+            .call_tool(&tool_call("rm_rf", json!({})), &[], &ctx)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "tool error: unknown tool: rm_rf");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_file_text_tool_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ctx = context(&state).await?;
+        let tools = [ChatTool::FetchFileText];
+
+        let file = ChatFile::new(1, "notes.txt", b"meeting at 10am");
+        let path = file.path(&state.config.server.base_dir);
+        std::fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
+        std::fs::write(&path, b"meeting at 10am")?;
+
+        let fetch = tool_call("fetch_file_text", json!({ "url": file.url() }));
+        assert_eq!(
+            state.call_tool(&fetch, &tools, &ctx).await?,
+            "meeting at 10am"
+        );
+
+        // files of another workspace are not visible
+        let other = ChatFile::new(2, "notes.txt", b"meeting at 10am");
+        let fetch = tool_call("fetch_file_text", json!({ "url": other.url() }));
+        assert!(state.call_tool(&fetch, &tools, &ctx).await.is_err());
+
+        let fetch = tool_call("fetch_file_text", json!({}));
+        let err = state.call_tool(&fetch, &tools, &ctx).await.unwrap_err();
+        assert!(err.to_string().starts_with("tool error: invalid arguments"));
+        Ok(())
+    }
+}
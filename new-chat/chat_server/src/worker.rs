@@ -0,0 +1,725 @@
+use crate::{
+    AppError, AppState,
+    agent::{AgentVariant, TapSink},
+    models::{AgentRun, AgentRunStatus, check_agent_host},
+};
+use ai_sdk::{CompletionChunk, Usage};
+use chat_core::{AgentContext, AgentDecision, AgentError, ChatAgent, ChatType, Message};
+use futures_util::{StreamExt, stream};
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+use tokio::{task::JoinHandle, time};
+use tracing::{debug, info, warn};
+
+impl AppState {
+    /// Spawn the background worker that runs chat agents on newly sent messages,
+    /// off the request path.
+    pub fn spawn_agent_worker(&self) -> JoinHandle<()> {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let config = &state.config.agent_worker;
+            let interval = Duration::from_millis(config.poll_interval_ms);
+            // replies still pending after a run's lease belong to a run that was abandoned,
+            // looked for every tenth of the lease while the queue is empty
+            let max_pending_age = Duration::from_secs(config.lease_secs);
+            let mut last_sweep = Instant::now();
+            loop {
+                match state.run_next_agent_job().await {
+                    // keep draining the queue
+                    Ok(true) => continue,
+                    Ok(false) => {
+                        if last_sweep.elapsed() >= max_pending_age / 10 {
+                            last_sweep = Instant::now();
+                            if let Err(e) = state.sweep_pending_messages(max_pending_age).await {
+                                warn!("failed to sweep pending messages: {}", e);
+                            }
+                        }
+                        time::sleep(interval).await
+                    }
+                    Err(e) => {
+                        warn!("agent worker failed: {}", e);
+                        time::sleep(interval).await;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Claim and run one due agent run, returns false if there is nothing to do.
+    pub async fn run_next_agent_job(&self) -> Result<bool, AppError> {
+        let config = &self.config.agent_worker;
+        let lease = Duration::from_secs(config.lease_secs);
+        let Some(run) = self.claim_agent_run(lease).await? else {
+            return Ok(false);
+        };
+
+        // attempts over the limit only happen if a worker died while running it
+        if run.attempts > config.max_attempts {
+            self.finish_agent_run(run.id, AgentRunStatus::Failed, Some("agent run abandoned"))
+                .await?;
+            return Ok(true);
+        }
+
+        let last_attempt = run.attempts >= config.max_attempts;
+        match self.run_agent_pipeline(&run, last_attempt).await {
+            Ok(errors) if errors.is_empty() => {
+                self.finish_agent_run(run.id, AgentRunStatus::Succeeded, None)
+                    .await?;
+            }
+            Ok(errors) => {
+                self.finish_agent_run(run.id, AgentRunStatus::Failed, Some(&errors.join("; ")))
+                    .await?;
+            }
+            Err(e) if !last_attempt => {
+                warn!("agent run {} failed, retrying: {}", run.id, e);
+                let delay = Duration::from_secs(1 << run.attempts.clamp(0, 10));
+                self.retry_agent_run(run.id, &e.to_string(), delay).await?;
+            }
+            Err(e) => {
+                warn!("agent run {} failed: {}", run.id, e);
+                self.finish_agent_run(run.id, AgentRunStatus::Failed, Some(&e.to_string()))
+                    .await?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Run the chat's agents in order on the message of a run: proxies are chained so
+    /// each one sees the previous modification, reply and tap agents run once the
+    /// modified content is applied.
+    ///
+    /// Errors before anything is written (a failing proxy, a reply agent that didn't
+    /// stream anything) make the whole run retryable. On the last attempt, for errors
+    /// that won't go away (e.g. a bad request), and once a reply / tap agent wrote
+    /// something, failures are returned as a list instead and the other agents still run.
+    async fn run_agent_pipeline(
+        &self,
+        run: &AgentRun,
+        last_attempt: bool,
+    ) -> Result<Vec<String>, AppError> {
+        // nothing to do for messages deleted in the meantime
+        let message = self.get_message_by_id(run.message_id as _).await?;
+        let Some(message) = message.filter(|m| m.deleted_at.is_none()) else {
+            return Ok(vec![]);
+        };
+        let agents = self.list_agents(run.chat_id as _).await?;
+        let ctx = self.load_agent_context(&message).await?;
+
+        let mut errors = vec![];
+        let mut modified_content: Option<String> = None;
+        let mut followers = vec![];
+        for agent in agents {
+            // agents saved before hosts were checked
+            if let Err(e) = check_agent_host(&agent.args, &self.config.agent_hosts) {
+                errors.push(format!("{}: {}", agent.name, e));
+                continue;
+            }
+            match AgentVariant::from(agent.clone()) {
+                AgentVariant::Proxy(proxy) => {
+                    if let Err(e) = self.consume_ai_request(ctx.ws_id as _).await {
+                        errors.push(format!("{}: {}", agent.name, e));
+                        continue;
+                    }
+                    let content = modified_content.as_deref().unwrap_or(&message.content);
+                    let started = Instant::now();
+                    let result = self
+                        .with_timeout(proxy.process_with_usage(content, &ctx))
+                        .await;
+                    let usage = result.as_ref().ok().map(|(_, usage)| *usage);
+                    self.track_usage(&agent, Some(message.id as _), &ctx, started, usage)
+                        .await;
+                    match result {
+                        Ok((AgentDecision::Modify(s), _)) => modified_content = Some(s),
+                        Ok(_) => {}
+                        Err(e) if !last_attempt && e.is_retryable() => return Err(e.into()),
+                        Err(e) => {
+                            warn!(
+                                "proxy agent {} failed on message {}: {}",
+                                agent.name, message.id, e
+                            );
+                            errors.push(format!("{}: {}", agent.name, e));
+                        }
+                    }
+                }
+                // moderation agents checked the message before it was sent
+                AgentVariant::Moderate(_) => {}
+                // reply agents answer the agents mentioned, or anything in a single chat
+                AgentVariant::Reply(_)
+                    if !replies_to_message(&agent, &run.mentions, &ctx.chat_type) => {}
+                variant => followers.push((agent, variant)),
+            }
+        }
+
+        let content = match modified_content {
+            Some(content) => {
+                if self
+                    .set_modified_content(message.id as _, &content)
+                    .await?
+                    .is_none()
+                {
+                    // deleted while the proxy agents ran
+                    return Ok(errors);
+                }
+                content
+            }
+            None => message.content.clone(),
+        };
+
+        // whether a reply / tap agent wrote something, the run can't be retried anymore
+        let mut written = false;
+        for (agent, variant) in followers {
+            if let Err(e) = self.consume_ai_request(ctx.ws_id as _).await {
+                errors.push(format!("{}: {}", agent.name, e));
+                continue;
+            }
+            let started = Instant::now();
+            let result = self
+                .run_agent(&agent, variant, &message, &content, &ctx)
+                .await;
+            let usage = result.as_ref().ok().copied();
+            self.track_usage(&agent, Some(message.id as _), &ctx, started, usage)
+                .await;
+            match result {
+                Ok(_) => written = true,
+                // agent errors are returned once anything written is removed
+                Err(AppError::AiAgentError(e)) if !last_attempt && !written && e.is_retryable() => {
+                    return Err(e.into());
+                }
+                Err(e) => {
+                    warn!(
+                        "agent {} failed on message {}: {}",
+                        agent.name, message.id, e
+                    );
+                    errors.push(format!("{}: {}", agent.name, e));
+                }
+            }
+        }
+
+        Ok(errors)
+    }
+
+    /// run a reply / tap agent on a message that was already created
+    async fn run_agent(
+        &self,
+        chat_agent: &ChatAgent,
+        agent: AgentVariant,
+        message: &Message,
+        msg: &str,
+        ctx: &AgentContext,
+    ) -> Result<Usage, AppError> {
+        match agent {
+            // stream the reply into a pending message, sent as the agent's bot user and
+            // quoting the message outside of single chats, in the thread of the message
+            AgentVariant::Reply(agent) => {
+                let bot_id = self.agent_bot_id(chat_agent).await?;
+                let reply_to_id = (ctx.chat_type != ChatType::Single).then_some(message.id);
+                let reply = self
+                    .create_pending_message(
+                        ctx.chat_id as _,
+                        bot_id,
+                        Some(chat_agent.id),
+                        reply_to_id,
+                        message.parent_id,
+                    )
+                    .await?;
+                let stream = if agent.tools.is_empty() {
+                    self.with_timeout(agent.process_stream(msg, ctx))
+                        .await
+                        .map_err(AppError::from)
+                } else {
+                    // tool rounds aren't streamed, the answer is sent as a single delta
+                    agent
+                        .process_with_tools(self, msg, ctx)
+                        .await
+                        .map(|completion| {
+                            let chunks = [
+                                Ok(CompletionChunk::Delta(completion.content)),
+                                Ok(CompletionChunk::Usage(completion.usage)),
+                            ];
+                            stream::iter(chunks).boxed()
+                        })
+                };
+                match stream {
+                    Ok(stream) => {
+                        let members = ctx.members.iter().map(|m| m.id).collect::<Vec<_>>();
+                        let (_, usage) = self.stream_reply(reply, &members, stream).await?;
+                        Ok(usage)
+                    }
+                    Err(e) => {
+                        self.discard_pending_message(reply.id as _).await?;
+                        Err(e)
+                    }
+                }
+            }
+            // hand the tap output to its sink
+            AgentVariant::Tap(agent) => {
+                let (AgentDecision::Annotate(value), usage) = self
+                    .with_timeout(agent.process_with_usage(msg, ctx))
+                    .await?
+                else {
+                    return Ok(Usage::default());
+                };
+                match agent.sink() {
+                    TapSink::Annotation => {
+                        self.create_annotation(
+                            message.id as _,
+                            Some(agent.id),
+                            agent.kind(),
+                            value,
+                        )
+                        .await?;
+                    }
+                    TapSink::Log => info!(
+                        "tap agent {} on message {}: {} = {}",
+                        agent.name,
+                        message.id,
+                        agent.kind(),
+                        value
+                    ),
+                }
+                Ok(usage)
+            }
+            agent => {
+                let (decision, usage) = self
+                    .with_timeout(agent.process_with_usage(msg, ctx))
+                    .await?;
+                debug!("agent decision in chat {}: {:?}", ctx.chat_id, decision);
+                Ok(usage)
+            }
+        }
+    }
+
+    /// record the usage of an agent call started at `started`, `None` if it failed,
+    /// and charge its tokens to the workspace quota. Accounting doesn't fail the run.
+    pub(crate) async fn track_usage(
+        &self,
+        agent: &ChatAgent,
+        message_id: Option<u64>,
+        ctx: &AgentContext,
+        started: Instant,
+        usage: Option<Usage>,
+    ) {
+        let latency = started.elapsed();
+        let (usage, succeeded) = match usage {
+            Some(usage) => (usage, true),
+            None => (Usage::default(), false),
+        };
+        if let Err(e) = self
+            .record_agent_usage(agent, message_id, usage, latency, succeeded)
+            .await
+        {
+            warn!("failed to record usage of agent {}: {}", agent.name, e);
+        }
+        if let Err(e) = self
+            .record_ai_tokens(ctx.ws_id as _, usage.total_tokens())
+            .await
+        {
+            warn!("failed to charge tokens of agent {}: {}", agent.name, e);
+        }
+    }
+
+    /// bound a single agent call by the configured timeout
+    pub(crate) async fn with_timeout<T>(
+        &self,
+        fut: impl Future<Output = Result<T, AgentError>>,
+    ) -> Result<T, AgentError> {
+        let timeout = Duration::from_secs(self.config.agent_worker.timeout_secs);
+        time::timeout(timeout, fut)
+            .await
+            .map_err(|_| AgentError::Timeout(timeout))?
+    }
+}
+
+/// Whether a reply agent answers a message: if the message mentions agents only the
+/// mentioned ones do, otherwise they only answer in single chats.
+fn replies_to_message(agent: &ChatAgent, mentions: &[i64], chat_type: &ChatType) -> bool {
+    if mentions.is_empty() {
+        *chat_type == ChatType::Single
+    } else {
+        mentions.contains(&agent.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CreateAgent, CreateMessage, ListMessages, UsageQuery};
+    use anyhow::Result;
+    use chat_core::{AdapterType, AgentType};
+
+    #[tokio::test]
+    async fn agent_pipeline_should_apply_decisions() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 3 is a single chat between user 1 and 2
+        let agents = [
+            (
+                "polite",
+                AgentType::Proxy,
+                serde_json::json!({ "script": { "mode": "rewrite", "pattern": "^hey", "replacement": "hello" } }),
+            ),
+            (
+                "assistant",
+                AgentType::Reply,
+                serde_json::json!({ "script": { "mode": "fixed", "reply": "Hi, how can I help?" } }),
+            ),
+            (
+                "mood",
+                AgentType::Tap,
+                serde_json::json!({ "script": { "mode": "fixed", "reply": "positive" }, "kind": "sentiment" }),
+            ),
+        ];
+        let mut created = vec![];
+        for (name, r#type, args) in agents {
+            let input = CreateAgent::new(name, r#type, AdapterType::Test, "scripted", "", args);
+            created.push(state.create_agent(input, 3).await?);
+        }
+
+        let input = CreateMessage {
+            content: "hey there".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        let message = state.create_message(input, 3, 1).await?;
+        assert_eq!(message.modified_content, None);
+
+        assert!(state.run_next_agent_job().await?);
+        let runs = state.list_agent_runs(message.id as _).await?;
+        assert_eq!(runs[0].status, AgentRunStatus::Succeeded);
+
+        let message = state
+            .get_message_by_id(message.id as _)
+            .await?
+            .expect("message should exist");
+        assert_eq!(message.modified_content.as_deref(), Some("hello there"));
+
+        // the reply is sent as the bot user of the agent
+        let input = ListMessages {
+            last_id: None,
+            limit: 1,
+        };
+        let reply = state.list_messages(input, 3).await?.remove(0);
+        let assistant = state
+            .find_agent_by_id(3, created[1].id as _)
+            .await?
+            .expect("agent should exist");
+        let bot_id = assistant.bot_id.expect("bot user should be created");
+        assert_eq!(reply.sender_id, bot_id);
+        assert_eq!(reply.agent_id, Some(assistant.id));
+        assert_eq!(reply.content, "Hi, how can I help?");
+        assert!(!reply.is_pending);
+        let bot = state
+            .find_user_by_id(bot_id)
+            .await?
+            .expect("bot should exist");
+        assert_eq!(bot.fullname, "assistant");
+        assert!(bot.is_bot);
+        assert_eq!(state.agent_bot_id(&assistant).await?, bot_id);
+
+        let annotations = state.list_annotations(3, message.id as _).await?;
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0].kind, "sentiment");
+        assert_eq!(annotations[0].value.0, serde_json::json!("positive"));
+
+        // every agent call is accounted, the scripted adapter counts words as tokens
+        let usage = state.get_workspace_usage(1, &UsageQuery::default()).await?;
+        assert_eq!(usage.agents.len(), 3);
+        assert_eq!(usage.total.requests, 3);
+        assert_eq!(usage.total.failures, 0);
+        let reply = usage
+            .agents
+            .iter()
+            .find(|a| a.agent_name.as_deref() == Some("assistant"))
+            .expect("reply agent usage should exist");
+        assert_eq!(reply.completion_tokens, 5);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reply_agents_should_answer_mentions_in_group_chat() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 4 is a group of user 1, 3 and 4
+        let mut agents = vec![];
+        for (name, reply) in [("helper", "On it"), ("reviewer", "Looks good")] {
+            let input = CreateAgent::new(
+                name,
+                AgentType::Reply,
+                AdapterType::Test,
+                "scripted",
+                "",
+                serde_json::json!({ "script": { "mode": "fixed", "reply": reply } }),
+            );
+            agents.push(state.create_agent(input, 4).await?);
+        }
+        let mut sent = vec![];
+        for (sender_id, content) in [(1, "can someone help?"), (3, "@helper me too")] {
+            let input = CreateMessage {
+                content: content.to_string(),
+                files: vec![],
+                parent_id: None,
+            };
+            sent.push(state.create_message(input, 4, sender_id).await?);
+            assert!(state.run_next_agent_job().await?);
+        }
+        let runs = state.list_agent_runs(sent[1].id as _).await?;
+        assert_eq!(runs[0].mentions, vec![agents[0].id]);
+
+        // only the mentioned agent answers, quoting the message, as a bot user
+        let input = ListMessages {
+            last_id: None,
+            limit: 4,
+        };
+        let messages = state.list_messages(input, 4).await?;
+        let replies = messages
+            .iter()
+            .filter(|m| m.agent_id.is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].agent_id, Some(agents[0].id));
+        assert_eq!(replies[0].reply_to_id, Some(sent[1].id));
+        assert_eq!(replies[0].content, "On it");
+        assert!(![1, 3, 4].contains(&replies[0].sender_id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mentioning_proxy_agent_should_not_silence_reply_agents() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 3 is a single chat, where reply agents answer unless others are mentioned
+        let agents = [
+            ("polite", AgentType::Proxy, "Hello"),
+            ("assistant", AgentType::Reply, "Hi, how can I help?"),
+        ];
+        let mut created = vec![];
+        for (name, r#type, reply) in agents {
+            let input = CreateAgent::new(
+                name,
+                r#type,
+                AdapterType::Test,
+                "scripted",
+                "",
+                serde_json::json!({ "script": { "mode": "fixed", "reply": reply } }),
+            );
+            created.push(state.create_agent(input, 3).await?);
+        }
+        let input = CreateMessage {
+            content: "@polite hey".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        let message = state.create_message(input, 3, 1).await?;
+        assert!(state.run_next_agent_job().await?);
+        let runs = state.list_agent_runs(message.id as _).await?;
+        assert!(runs[0].mentions.is_empty());
+
+        let input = ListMessages {
+            last_id: None,
+            limit: 1,
+        };
+        let reply = state.list_messages(input, 3).await?.remove(0);
+        assert_eq!(reply.agent_id, Some(created[1].id));
+        assert_eq!(reply.content, "Hi, how can I help?");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sequence_agent_should_go_on_across_runs() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 3 is a single chat, the reply agent answers every message
+        let input = CreateAgent::new(
+            "counter",
+            AgentType::Reply,
+            AdapterType::Test,
+            "scripted",
+            "",
+            serde_json::json!({ "script": { "mode": "sequence", "replies": ["one", "two"] } }),
+        );
+        state.create_agent(input, 3).await?;
+        for content in ["first", "second"] {
+            let input = CreateMessage {
+                content: content.to_string(),
+                files: vec![],
+                parent_id: None,
+            };
+            state.create_message(input, 3, 1).await?;
+            assert!(state.run_next_agent_job().await?);
+        }
+
+        let input = ListMessages {
+            last_id: None,
+            limit: 4,
+        };
+        let mut replies = state
+            .list_messages(input, 3)
+            .await?
+            .into_iter()
+            .filter(|m| m.agent_id.is_some())
+            .map(|m| m.content)
+            .collect::<Vec<_>>();
+        replies.reverse();
+        assert_eq!(replies, vec!["one", "two"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn agent_run_with_failing_agent_should_be_retried() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // the proxy agent fails as if its model server was down
+        let input = CreateAgent::new(
+            "broken",
+            AgentType::Proxy,
+            AdapterType::Test,
+            "scripted",
+            "You are a helpful assistant",
+            serde_json::json!({ "script": { "mode": "fail", "error": "connection refused" } }),
+        );
+        state.create_agent(input, 3).await?;
+
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        let message = state.create_message(input, 3, 1).await?;
+
+        assert!(state.run_next_agent_job().await?);
+        let runs = state.list_agent_runs(message.id as _).await?;
+        assert_eq!(runs[0].status, AgentRunStatus::Pending);
+        assert_eq!(runs[0].attempts, 1);
+        assert!(runs[0].error.is_some());
+        // not due before the backoff
+        assert!(!state.run_next_agent_job().await?);
+
+        let max_attempts = state.config.agent_worker.max_attempts;
+        for _ in 1..max_attempts {
+            sqlx::query("UPDATE agent_runs SET run_at = NOW() WHERE id = $1")
+                .bind(runs[0].id)
+                .execute(&state.pool)
+                .await?;
+            assert!(state.run_next_agent_job().await?);
+        }
+        let runs = state.list_agent_runs(message.id as _).await?;
+        assert_eq!(runs[0].status, AgentRunStatus::Failed);
+        assert_eq!(runs[0].attempts, max_attempts);
+        assert!(
+            runs[0]
+                .error
+                .as_deref()
+                .unwrap_or_default()
+                .starts_with("broken:")
+        );
+
+        let message = state
+            .get_message_by_id(message.id as _)
+            .await?
+            .expect("message should exist");
+        assert_eq!(message.modified_content, None);
+
+        // failed calls are accounted too
+        let usage = state.get_workspace_usage(1, &UsageQuery::default()).await?;
+        assert_eq!(usage.total.requests, max_attempts as i64);
+        assert_eq!(usage.total.failures, max_attempts as i64);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stalled_reply_stream_should_not_hold_worker() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let state = state.with_config(|config| config.agent_worker.timeout_secs = 1);
+        // chat 3 is a single chat, the reply agent answers and never finishes
+        let input = CreateAgent::new(
+            "stuck",
+            AgentType::Reply,
+            AdapterType::Test,
+            "scripted",
+            "",
+            serde_json::json!({ "script": { "mode": "stall", "reply": "Hello there" } }),
+        );
+        let agent = state.create_agent(input, 3).await?;
+        let input = CreateMessage {
+            content: "hi".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        let message = state.create_message(input, 3, 1).await?;
+
+        let ran = time::timeout(Duration::from_secs(10), state.run_next_agent_job()).await;
+        assert!(ran.expect("worker should not hang")?);
+
+        // the reply keeps what was streamed
+        let input = ListMessages {
+            last_id: None,
+            limit: 1,
+        };
+        let reply = state.list_messages(input, 3).await?.remove(0);
+        assert_eq!(reply.agent_id, Some(agent.id));
+        assert_eq!(reply.content, "Hello there");
+        assert!(!reply.is_pending);
+        // the stall is recorded, the partial reply isn't written again
+        let runs = state.list_agent_runs(message.id as _).await?;
+        assert_eq!(runs[0].status, AgentRunStatus::Failed);
+        assert!(
+            runs[0]
+                .error
+                .as_deref()
+                .unwrap_or_default()
+                .starts_with("stuck: ")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_reply_should_be_removed_and_retried() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 3 is a single chat, the reply agent's model server is down
+        let input = CreateAgent::new(
+            "broken",
+            AgentType::Reply,
+            AdapterType::Test,
+            "scripted",
+            "",
+            serde_json::json!({ "script": { "mode": "fail", "error": "connection refused" } }),
+        );
+        state.create_agent(input, 3).await?;
+        let input = CreateMessage {
+            content: "hi".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        let message = state.create_message(input, 3, 1).await?;
+
+        let max_attempts = state.config.agent_worker.max_attempts;
+        for attempt in 1..=max_attempts {
+            assert!(state.run_next_agent_job().await?);
+            let runs = state.list_agent_runs(message.id as _).await?;
+            assert_eq!(runs[0].attempts, attempt);
+            assert!(runs[0].error.is_some());
+            sqlx::query("UPDATE agent_runs SET run_at = NOW() WHERE id = $1")
+                .bind(runs[0].id)
+                .execute(&state.pool)
+                .await?;
+        }
+        let runs = state.list_agent_runs(message.id as _).await?;
+        assert_eq!(runs[0].status, AgentRunStatus::Failed);
+
+        // every attempt's pending reply is a tombstone, none is left empty or pending
+        let input = ListMessages {
+            last_id: None,
+            limit: 10,
+        };
+        let replies = state
+            .list_messages(input, 3)
+            .await?
+            .into_iter()
+            .filter(|m| m.agent_id.is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(replies.len(), max_attempts as usize);
+        assert!(
+            replies
+                .iter()
+                .all(|m| m.deleted_at.is_some() && !m.is_pending)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+-- Add migration script here
+
+-- pending messages are placeholders filled in by a streaming agent reply
+ALTER TABLE messages
+    ADD COLUMN is_pending BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- if a message is updated (e.g. a pending reply is finalized), notify with message data
+CREATE OR REPLACE FUNCTION message_updated()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  IF TG_OP = 'UPDATE' THEN
+    RAISE NOTICE 'message_updated: %', NEW;
+    SELECT
+      members INTO USERS
+    FROM
+      chats
+    WHERE
+      id = NEW.chat_id;
+    PERFORM
+      pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', USERS)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_updated_trigger
+  AFTER UPDATE ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION message_updated();
@@ -0,0 +1,16 @@
+-- Add migration script here
+
+-- agents of a chat run in ascending position order
+ALTER TABLE chat_agents
+    ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+-- keep the current (creation) order for existing agents
+UPDATE chat_agents
+SET position = ordered.rn
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY chat_id ORDER BY id) AS rn
+    FROM chat_agents
+) AS ordered
+WHERE chat_agents.id = ordered.id;
+
+CREATE INDEX IF NOT EXISTS chat_agents_chat_id_position_index ON chat_agents(chat_id, position);
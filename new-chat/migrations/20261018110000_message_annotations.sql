@@ -0,0 +1,13 @@
+-- Add migration script here
+
+-- side outputs of tap agents (classification, sentiment, moderation flags, summaries...)
+CREATE TABLE IF NOT EXISTS message_annotations (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    agent_id BIGINT REFERENCES chat_agents(id) ON DELETE SET NULL,
+    kind VARCHAR(64) NOT NULL,
+    value JSONB NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS message_annotations_message_id_index ON message_annotations(message_id);
@@ -0,0 +1,27 @@
+-- Add migration script here
+
+-- agent pipeline runs, one per message sent to a chat with agents.
+-- the table doubles as a durable job queue for the agent worker.
+CREATE TYPE agent_run_status AS ENUM (
+  'pending',
+  'running',
+  'succeeded',
+  'failed'
+);
+
+CREATE TABLE IF NOT EXISTS agent_runs (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    status agent_run_status NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    -- a pending run is not picked up before this time (retry backoff)
+    run_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    started_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS agent_runs_status_run_at_index ON agent_runs(status, run_at);
+CREATE INDEX IF NOT EXISTS agent_runs_message_id_index ON agent_runs(message_id);
@@ -0,0 +1,21 @@
+-- Add migration script here
+
+-- token usage and latency of every agent invocation, kept when the agent,
+-- chat or message is deleted so workspace totals stay correct
+CREATE TABLE IF NOT EXISTS agent_usage (
+    id BIGSERIAL PRIMARY KEY,
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    chat_id BIGINT REFERENCES chats(id) ON DELETE SET NULL,
+    agent_id BIGINT REFERENCES chat_agents(id) ON DELETE SET NULL,
+    agent_name TEXT NOT NULL,
+    message_id BIGINT REFERENCES messages(id) ON DELETE SET NULL,
+    model VARCHAR(255) NOT NULL,
+    prompt_tokens INTEGER NOT NULL DEFAULT 0,
+    completion_tokens INTEGER NOT NULL DEFAULT 0,
+    latency_ms INTEGER NOT NULL,
+    succeeded BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS agent_usage_ws_id_created_at_index ON agent_usage(ws_id, created_at);
+CREATE INDEX IF NOT EXISTS agent_usage_agent_id_index ON agent_usage(agent_id);
@@ -0,0 +1,9 @@
+-- Add migration script here
+
+-- agents reply as their own bot user, created on their first reply
+ALTER TABLE chat_agents
+    ADD COLUMN bot_id BIGINT REFERENCES users(id) ON DELETE SET NULL;
+
+-- the agent that wrote a message, so clients can tell AI messages apart
+ALTER TABLE messages
+    ADD COLUMN agent_id BIGINT REFERENCES chat_agents(id) ON DELETE SET NULL;
@@ -0,0 +1,9 @@
+-- Add migration script here
+
+-- the message a message quotes, e.g. an agent reply to the message mentioning it
+ALTER TABLE messages
+    ADD COLUMN reply_to_id BIGINT REFERENCES messages(id) ON DELETE SET NULL;
+
+-- agents mentioned by the message of a run, by id
+ALTER TABLE agent_runs
+    ADD COLUMN mentions BIGINT[] NOT NULL DEFAULT '{}';
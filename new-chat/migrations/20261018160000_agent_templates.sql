@@ -0,0 +1,43 @@
+-- Add migration script here
+
+-- agent names only have to be unique within their chat
+ALTER TABLE chat_agents
+    DROP CONSTRAINT IF EXISTS chat_agents_name_key;
+ALTER TABLE chat_agents
+    ADD CONSTRAINT chat_agents_chat_id_name_key UNIQUE (chat_id, name);
+
+-- workspace agent definitions, attached to chats as chat agents
+CREATE TABLE IF NOT EXISTS agent_templates (
+    id BIGSERIAL PRIMARY KEY,
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    type agent_type NOT NULL DEFAULT 'reply',
+    adapter adapter_type NOT NULL DEFAULT 'ollama',
+    model VARCHAR(255) NOT NULL,
+    prompt TEXT NOT NULL,
+    args JSONB NOT NULL DEFAULT '{}',
+    -- the latest version in agent_template_versions
+    version INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (ws_id, name)
+);
+
+-- every definition a template had
+CREATE TABLE IF NOT EXISTS agent_template_versions (
+    id BIGSERIAL PRIMARY KEY,
+    template_id BIGINT NOT NULL REFERENCES agent_templates(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    model VARCHAR(255) NOT NULL,
+    prompt TEXT NOT NULL,
+    args JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (template_id, version)
+);
+
+-- chat agents attached to a template follow it, except for their overrides
+ALTER TABLE chat_agents
+    ADD COLUMN template_id BIGINT REFERENCES agent_templates(id) ON DELETE SET NULL,
+    ADD COLUMN overrides JSONB NOT NULL DEFAULT '{}';
+
+CREATE INDEX IF NOT EXISTS chat_agents_template_id_index ON chat_agents(template_id);
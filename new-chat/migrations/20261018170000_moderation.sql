@@ -0,0 +1,26 @@
+-- Add migration script here
+
+-- moderation agents check messages before they are sent
+ALTER TYPE agent_type ADD VALUE IF NOT EXISTS 'moderate';
+
+CREATE TYPE moderation_action AS ENUM ('reject', 'redact', 'flag');
+
+-- messages moderation agents acted on, reviewed by the workspace owners
+CREATE TABLE IF NOT EXISTS moderation_reviews (
+    id BIGSERIAL PRIMARY KEY,
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    -- not set for rejected messages, they are never sent
+    message_id BIGINT REFERENCES messages(id) ON DELETE SET NULL,
+    sender_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    agent_id BIGINT REFERENCES chat_agents(id) ON DELETE SET NULL,
+    action moderation_action NOT NULL,
+    reason TEXT NOT NULL DEFAULT '',
+    -- the content as sent by the user, before any redaction
+    content TEXT NOT NULL,
+    resolved_by BIGINT REFERENCES users(id) ON DELETE SET NULL,
+    resolved_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS moderation_reviews_ws_id_index ON moderation_reviews(ws_id, resolved_at, id);
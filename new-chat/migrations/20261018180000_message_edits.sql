@@ -0,0 +1,15 @@
+-- Add migration script here
+
+-- set when the sender edits a message, members get the edit as an update event
+ALTER TABLE messages
+    ADD COLUMN edited_at TIMESTAMPTZ;
+
+-- previous versions of edited messages
+CREATE TABLE IF NOT EXISTS message_edits (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS message_edits_message_id_index ON message_edits(message_id);
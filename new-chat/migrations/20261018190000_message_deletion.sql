@@ -0,0 +1,38 @@
+-- Add migration script here
+
+-- the user who created a chat, may delete any message in it
+ALTER TABLE chats
+    ADD COLUMN owner_id BIGINT REFERENCES users(id) ON DELETE SET NULL;
+
+-- deleted messages are kept as tombstones without content
+ALTER TABLE messages
+    ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- a message turned into a tombstone is announced as deleted, updates of live
+-- messages with the message data
+CREATE OR REPLACE FUNCTION message_updated()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  IF TG_OP = 'UPDATE' THEN
+    RAISE NOTICE 'message_updated: %', NEW;
+    SELECT
+      members INTO USERS
+    FROM
+      chats
+    WHERE
+      id = NEW.chat_id;
+    IF NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN
+      PERFORM
+        pg_notify('chat_message_deleted', json_build_object('chat_id', NEW.chat_id, 'message_id', NEW.id, 'members', USERS)::text);
+    ELSIF NEW.deleted_at IS NULL THEN
+      PERFORM
+        pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', USERS)::text);
+    END IF;
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
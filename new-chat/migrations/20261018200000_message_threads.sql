@@ -0,0 +1,62 @@
+-- Add migration script here
+
+-- replies in the thread of a top level message, counted on the parent
+ALTER TABLE messages
+    ADD COLUMN parent_id BIGINT REFERENCES messages(id) ON DELETE SET NULL,
+    ADD COLUMN reply_count INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN last_reply_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS messages_parent_id_index ON messages(parent_id, id);
+
+-- keep the reply count of the parent up to date, members get it as an update event
+CREATE OR REPLACE FUNCTION message_thread_updated()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    UPDATE messages
+    SET reply_count = reply_count + 1, last_reply_at = NEW.created_at
+    WHERE id = NEW.parent_id;
+  ELSIF NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN
+    UPDATE messages
+    SET reply_count = reply_count - 1
+    WHERE id = NEW.parent_id;
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_thread_trigger
+  AFTER INSERT OR UPDATE OF deleted_at ON messages
+  FOR EACH ROW
+  WHEN (NEW.parent_id IS NOT NULL)
+  EXECUTE FUNCTION message_thread_updated();
+
+-- deletions carry the thread of the message
+CREATE OR REPLACE FUNCTION message_updated()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  IF TG_OP = 'UPDATE' THEN
+    RAISE NOTICE 'message_updated: %', NEW;
+    SELECT
+      members INTO USERS
+    FROM
+      chats
+    WHERE
+      id = NEW.chat_id;
+    IF NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN
+      PERFORM
+        pg_notify('chat_message_deleted', json_build_object('chat_id', NEW.chat_id, 'message_id', NEW.id, 'parent_id', NEW.parent_id, 'members', USERS)::text);
+    ELSIF NEW.deleted_at IS NULL THEN
+      PERFORM
+        pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', USERS)::text);
+    END IF;
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,47 @@
+-- Add migration script here
+
+-- emoji reactions of users to messages, once per user and emoji
+CREATE TABLE IF NOT EXISTS message_reactions (
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    emoji VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, emoji, user_id)
+);
+
+-- if a reaction is added or removed, notify the members of the chat; reactions
+-- cleared from a deleted message are covered by chat_message_deleted
+CREATE OR REPLACE FUNCTION reaction_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  REACTION record;
+  CHAT bigint;
+  USERS bigint[];
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    REACTION := NEW;
+  ELSE
+    REACTION := OLD;
+  END IF;
+  SELECT
+    m.chat_id, c.members INTO CHAT, USERS
+  FROM
+    messages m
+    JOIN chats c ON c.id = m.chat_id
+  WHERE
+    m.id = REACTION.message_id
+    AND m.deleted_at IS NULL;
+  IF FOUND THEN
+    PERFORM
+      pg_notify('chat_reaction_changed', json_build_object('chat_id', CHAT, 'message_id', REACTION.message_id, 'user_id', REACTION.user_id, 'emoji', REACTION.emoji, 'added', TG_OP = 'INSERT', 'members', USERS)::text);
+  END IF;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER reaction_changed_trigger
+  AFTER INSERT OR DELETE ON message_reactions
+  FOR EACH ROW
+  EXECUTE FUNCTION reaction_changed();
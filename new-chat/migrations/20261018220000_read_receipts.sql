@@ -0,0 +1,39 @@
+-- Add migration script here
+
+-- the last message a member has read in a chat, messages after it are unread
+CREATE TABLE IF NOT EXISTS chat_reads (
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    last_read_id BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, user_id)
+);
+
+-- if a member reads a single or group chat, notify the other members; channels
+-- are too large for receipts
+CREATE OR REPLACE FUNCTION read_receipt()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  SELECT
+    array_remove(members, NEW.user_id) INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id
+    AND type IN ('single', 'group');
+  IF FOUND THEN
+    PERFORM
+      pg_notify('chat_read_receipt', json_build_object('chat_id', NEW.chat_id, 'user_id', NEW.user_id, 'message_id', NEW.last_read_id, 'read_at', NEW.updated_at, 'members', USERS)::text);
+  END IF;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER read_receipt_trigger
+  AFTER INSERT OR UPDATE OF last_read_id ON chat_reads
+  FOR EACH ROW
+  EXECUTE FUNCTION read_receipt();
@@ -0,0 +1,6 @@
+-- Add migration script here
+
+-- the top level messages of a chat, listed without their thread replies
+CREATE INDEX IF NOT EXISTS messages_top_level_index ON messages(chat_id, id)
+WHERE
+  parent_id IS NULL;
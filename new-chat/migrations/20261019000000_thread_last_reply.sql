@@ -0,0 +1,24 @@
+-- Add migration script here
+
+-- a deleted reply no longer counts as the last one of its thread
+CREATE OR REPLACE FUNCTION message_thread_updated()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    UPDATE messages
+    SET reply_count = reply_count + 1, last_reply_at = NEW.created_at
+    WHERE id = NEW.parent_id;
+  ELSIF NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN
+    UPDATE messages
+    SET reply_count = reply_count - 1,
+      last_reply_at = (
+        SELECT MAX(created_at) FROM messages
+        WHERE parent_id = NEW.parent_id AND deleted_at IS NULL
+      )
+    WHERE id = NEW.parent_id;
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,9 @@
+-- Add migration script here
+
+-- only changes members see are notified, not e.g. the reply count of a thread
+DROP TRIGGER IF EXISTS message_updated_trigger ON messages;
+
+CREATE TRIGGER message_updated_trigger
+  AFTER UPDATE OF content, modified_content, files, is_pending, edited_at, deleted_at ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION message_updated();
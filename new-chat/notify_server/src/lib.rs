@@ -23,7 +23,7 @@ use tower_http::cors::{Any, CorsLayer};
 
 pub use config::AppConfig;
 pub use error::AppError;
-pub use notif::{AppEvent, setup_pg_listener, setup_redis_subscriber};
+pub use notif::{AppEvent, MessageDelta, setup_pg_listener, setup_redis_subscriber};
 
 pub type UserMap = Arc<DashMap<u64, broadcast::Sender<Arc<AppEvent>>>>;
 
@@ -1,6 +1,7 @@
 use crate::AppState;
 use anyhow::Result;
 use chat_core::{Chat, Message};
+use chrono::{DateTime, Utc};
 use futures_util::StreamExt;
 use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
@@ -29,6 +30,45 @@ pub struct UserJoinedWorkspace {
     pub user_email: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageDelta {
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub seq: u64,
+    pub chunk: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageDeleted {
+    pub chat_id: i64,
+    pub message_id: i64,
+    /// the thread the message was a reply in
+    pub parent_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionChanged {
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub user_id: i64,
+    pub emoji: String,
+    /// false if the user took the reaction back
+    pub added: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadReceipt {
+    pub chat_id: i64,
+    pub user_id: i64,
+    /// the last message the user read
+    pub message_id: i64,
+    pub read_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "event", rename_all = "camelCase")]
 pub enum AppEvent {
@@ -36,6 +76,11 @@ pub enum AppEvent {
     AddToChat(Chat),
     RemoveFromChat(Chat),
     NewMessage(Message),
+    MessageUpdated(Message),
+    MessageDeleted(MessageDeleted),
+    MessageDelta(MessageDelta),
+    ReactionChanged(ReactionChanged),
+    ReadReceipt(ReadReceipt),
     WorkspaceDeleted(WorkspaceDeleted),
     WorkspaceUpdated(WorkspaceUpdated),
     UserJoinedWorkspace(UserJoinedWorkspace),
@@ -60,6 +105,42 @@ struct ChatMessageCreated {
     members: Vec<i64>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatMessageDeletedPayload {
+    chat_id: i64,
+    message_id: i64,
+    parent_id: Option<i64>,
+    members: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatMessageDeltaPayload {
+    chat_id: i64,
+    message_id: i64,
+    seq: u64,
+    chunk: String,
+    members: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReactionChangedPayload {
+    chat_id: i64,
+    message_id: i64,
+    user_id: i64,
+    emoji: String,
+    added: bool,
+    members: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReadReceiptPayload {
+    chat_id: i64,
+    user_id: i64,
+    message_id: i64,
+    read_at: DateTime<Utc>,
+    members: Vec<i64>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct WorkspaceDeletedPayload {
     workspace: WorkspaceInfo,
@@ -102,6 +183,11 @@ pub async fn setup_pg_listener(state: AppState) -> Result<()> {
     let mut listener = PgListener::connect(&state.config.server.db_url).await?;
     listener.listen("chat_updated").await?;
     listener.listen("chat_message_created").await?;
+    listener.listen("chat_message_updated").await?;
+    listener.listen("chat_message_deleted").await?;
+    listener.listen("chat_message_delta").await?;
+    listener.listen("chat_reaction_changed").await?;
+    listener.listen("chat_read_receipt").await?;
     listener.listen("workspace_deleted").await?;
     listener.listen("workspace_updated").await?;
     listener.listen("user_joined_workspace").await?;
@@ -225,6 +311,72 @@ impl Notification {
                     event: Arc::new(AppEvent::NewMessage(payload.message)),
                 })
             }
+            "chat_message_updated" => {
+                // same payload shape as chat_message_created
+                let payload: ChatMessageCreated = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(AppEvent::MessageUpdated(payload.message)),
+                })
+            }
+            "chat_message_deleted" => {
+                let payload: ChatMessageDeletedPayload = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                let event = AppEvent::MessageDeleted(MessageDeleted {
+                    chat_id: payload.chat_id,
+                    message_id: payload.message_id,
+                    parent_id: payload.parent_id,
+                });
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(event),
+                })
+            }
+            "chat_message_delta" => {
+                let payload: ChatMessageDeltaPayload = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                let event = AppEvent::MessageDelta(MessageDelta {
+                    chat_id: payload.chat_id,
+                    message_id: payload.message_id,
+                    seq: payload.seq,
+                    chunk: payload.chunk,
+                });
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(event),
+                })
+            }
+            "chat_reaction_changed" => {
+                let payload: ReactionChangedPayload = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                let event = AppEvent::ReactionChanged(ReactionChanged {
+                    chat_id: payload.chat_id,
+                    message_id: payload.message_id,
+                    user_id: payload.user_id,
+                    emoji: payload.emoji,
+                    added: payload.added,
+                });
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(event),
+                })
+            }
+            "chat_read_receipt" => {
+                // members of single and group chats only, without the reader
+                let payload: ReadReceiptPayload = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                let event = AppEvent::ReadReceipt(ReadReceipt {
+                    chat_id: payload.chat_id,
+                    user_id: payload.user_id,
+                    message_id: payload.message_id,
+                    read_at: payload.read_at,
+                });
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(event),
+                })
+            }
             "workspace_deleted" => {
                 let payload: WorkspaceDeletedPayload = serde_json::from_str(payload)?;
                 info!("WorkspaceDeleted: {:?}", payload);
@@ -304,3 +456,148 @@ fn get_affected_chat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet
         _ => HashSet::new(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn user_ids(ids: &[u64]) -> HashSet<u64> {
+        ids.iter().copied().collect()
+    }
+
+    #[test]
+    fn message_updated_should_reach_members() -> Result<()> {
+        // the trigger sends the whole row of the message
+        let payload = json!({
+            "message": {
+                "id": 7,
+                "chat_id": 4,
+                "sender_id": 1,
+                "content": "lunch at noon?",
+                "modified_content": null,
+                "files": [],
+                "is_pending": false,
+                "agent_id": null,
+                "reply_to_id": null,
+                "parent_id": null,
+                "reply_count": 1,
+                "last_reply_at": "2026-10-18T12:01:00+00:00",
+                "edited_at": "2026-10-18T12:02:00+00:00",
+                "deleted_at": null,
+                "created_at": "2026-10-18T12:00:00+00:00"
+            },
+            "members": [1, 3, 4]
+        });
+        let notif = Notification::load("chat_message_updated", &payload.to_string())?;
+        assert_eq!(notif.user_ids, user_ids(&[1, 3, 4]));
+        let AppEvent::MessageUpdated(message) = notif.event.as_ref() else {
+            panic!("expected MessageUpdated, got {:?}", notif.event);
+        };
+        assert_eq!(message.id, 7);
+        assert_eq!(message.content, "lunch at noon?");
+        assert_eq!(message.reply_count, 1);
+        assert!(message.edited_at.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn message_deleted_should_reach_every_member() -> Result<()> {
+        let payload = json!({
+            "chat_id": 4,
+            "message_id": 8,
+            "parent_id": 7,
+            "members": [1, 3, 4]
+        });
+        let notif = Notification::load("chat_message_deleted", &payload.to_string())?;
+        assert_eq!(notif.user_ids, user_ids(&[1, 3, 4]));
+        let AppEvent::MessageDeleted(deleted) = notif.event.as_ref() else {
+            panic!("expected MessageDeleted, got {:?}", notif.event);
+        };
+        assert_eq!(deleted.chat_id, 4);
+        assert_eq!(deleted.message_id, 8);
+        assert_eq!(deleted.parent_id, Some(7));
+
+        // top level messages carry no thread
+        let payload = json!({ "chat_id": 4, "message_id": 7, "members": [1, 3, 4] });
+        let notif = Notification::load("chat_message_deleted", &payload.to_string())?;
+        let AppEvent::MessageDeleted(deleted) = notif.event.as_ref() else {
+            panic!("expected MessageDeleted, got {:?}", notif.event);
+        };
+        assert_eq!(deleted.parent_id, None);
+        Ok(())
+    }
+
+    #[test]
+    fn message_delta_should_reach_members() -> Result<()> {
+        let payload = json!({
+            "chat_id": 1,
+            "message_id": 9,
+            "seq": 3,
+            "chunk": "bonjour",
+            "members": [1, 2, 3, 4, 5]
+        });
+        let notif = Notification::load("chat_message_delta", &payload.to_string())?;
+        assert_eq!(notif.user_ids, user_ids(&[1, 2, 3, 4, 5]));
+        let AppEvent::MessageDelta(delta) = notif.event.as_ref() else {
+            panic!("expected MessageDelta, got {:?}", notif.event);
+        };
+        assert_eq!(delta.chat_id, 1);
+        assert_eq!(delta.message_id, 9);
+        assert_eq!(delta.seq, 3);
+        assert_eq!(delta.chunk, "bonjour");
+        Ok(())
+    }
+
+    #[test]
+    fn reaction_changed_should_reach_members() -> Result<()> {
+        let payload = json!({
+            "chat_id": 2,
+            "message_id": 5,
+            "user_id": 3,
+            "emoji": "👍",
+            "added": false,
+            "members": [1, 2, 3]
+        });
+        let notif = Notification::load("chat_reaction_changed", &payload.to_string())?;
+        assert_eq!(notif.user_ids, user_ids(&[1, 2, 3]));
+        let AppEvent::ReactionChanged(reaction) = notif.event.as_ref() else {
+            panic!("expected ReactionChanged, got {:?}", notif.event);
+        };
+        assert_eq!(reaction.message_id, 5);
+        assert_eq!(reaction.user_id, 3);
+        assert_eq!(reaction.emoji, "👍");
+        assert!(!reaction.added);
+        Ok(())
+    }
+
+    #[test]
+    fn read_receipt_should_reach_other_members() -> Result<()> {
+        // the trigger leaves the reader out of the members
+        let payload = json!({
+            "chat_id": 3,
+            "user_id": 2,
+            "message_id": 6,
+            "read_at": "2026-10-18T12:00:00.123456+00:00",
+            "members": [1]
+        });
+        let notif = Notification::load("chat_read_receipt", &payload.to_string())?;
+        assert_eq!(notif.user_ids, user_ids(&[1]));
+        let AppEvent::ReadReceipt(receipt) = notif.event.as_ref() else {
+            panic!("expected ReadReceipt, got {:?}", notif.event);
+        };
+        assert_eq!(receipt.chat_id, 3);
+        assert_eq!(receipt.user_id, 2);
+        assert_eq!(receipt.message_id, 6);
+        assert_eq!(
+            receipt.read_at.to_rfc3339(),
+            "2026-10-18T12:00:00.123456+00:00"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_channel_should_fail() {
+        assert!(Notification::load("chat_message_pinned", "{}").is_err());
+    }
+}
@@ -39,6 +39,11 @@ pub(crate) async fn sse_handler(
             AppEvent::AddToChat(_) => "AddToChat",
             AppEvent::RemoveFromChat(_) => "RemoveFromChat",
             AppEvent::NewMessage(_) => "NewMessage",
+            AppEvent::MessageUpdated(_) => "MessageUpdated",
+            AppEvent::MessageDeleted(_) => "MessageDeleted",
+            AppEvent::MessageDelta(_) => "MessageDelta",
+            AppEvent::ReactionChanged(_) => "ReactionChanged",
+            AppEvent::ReadReceipt(_) => "ReadReceipt",
             AppEvent::WorkspaceDeleted(_) => "WorkspaceDeleted",
             AppEvent::WorkspaceUpdated(_) => "WorkspaceUpdated",
             AppEvent::UserJoinedWorkspace(_) => "UserJoinedWorkspace",